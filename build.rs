@@ -0,0 +1,5 @@
+fn main() {
+    // Reported by `wrun --version --json`
+    let target = std::env::var("TARGET").unwrap();
+    println!("cargo::rustc-env=TARGET={target}");
+}
@@ -0,0 +1,202 @@
+//! Shell-style brace expansion, so `rm -rf {dist,build}` works the same no
+//! matter which shell runs it
+
+/// Expand every brace set like `{a,b}` in `command` into one word per
+/// alternative, eg `x{a,b}y` becomes `xay xby`. Sets can be nested. Braces
+/// without a top-level comma, inside quotes, or part of `${}` and `{{}}` are
+/// left alone.
+pub(crate) fn expand(command: &str) -> String {
+    let mut expanded = String::with_capacity(command.len());
+    for token in tokens(command) {
+        match token {
+            Token::Space(space) => expanded.push_str(space),
+            Token::Word(word) => expanded.push_str(&expand_word(word).join(" ")),
+        }
+    }
+    expanded
+}
+
+enum Token<'a> {
+    Word(&'a str),
+    Space(&'a str),
+}
+
+/// Split `command` into words and the whitespace between them, ignoring
+/// whitespace inside quotes
+fn tokens(command: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut quotes = Quotes::default();
+    let mut start = 0;
+    let mut in_space = false;
+
+    for (i, c) in command.char_indices() {
+        let space = c.is_whitespace() && !quotes.any();
+        quotes.update(c);
+        if space != in_space && i > start {
+            tokens.push(token(&command[start..i], in_space));
+            start = i;
+        }
+        in_space = space;
+    }
+    if start < command.len() {
+        tokens.push(token(&command[start..], in_space));
+    }
+    tokens
+}
+
+fn token(s: &str, space: bool) -> Token<'_> {
+    if space {
+        Token::Space(s)
+    } else {
+        Token::Word(s)
+    }
+}
+
+fn expand_word(word: &str) -> Vec<String> {
+    let Some((open, close, commas)) = find_set(word) else {
+        return vec![word.to_owned()];
+    };
+
+    let (prefix, suffix) = (&word[..open], &word[close + 1..]);
+    let mut bounds = vec![open];
+    bounds.extend(commas);
+    bounds.push(close);
+
+    bounds
+        .windows(2)
+        .flat_map(|bound| {
+            let alternative = &word[bound[0] + 1..bound[1]];
+            expand_word(&format!("{prefix}{alternative}{suffix}"))
+        })
+        .collect()
+}
+
+/// The first brace set in `word` that should be expanded, as the positions of
+/// its braces and top-level commas
+fn find_set(word: &str) -> Option<(usize, usize, Vec<usize>)> {
+    let bytes = word.as_bytes();
+    let mut quotes = Quotes::default();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        let quoted = quotes.any();
+        quotes.update(c as char);
+
+        if quoted || c != b'{' {
+            i += 1;
+            continue;
+        }
+
+        // Placeholders and shell parameters, skipped as a whole
+        let placeholder = bytes.get(i + 1) == Some(&b'{');
+        if placeholder || (i > 0 && bytes[i - 1] == b'$') {
+            let end = if placeholder { "}}" } else { "}" };
+            match word[i..].find(end) {
+                Some(offset) => i += offset + end.len(),
+                None => return None,
+            }
+            continue;
+        }
+
+        let mut depth = 0;
+        let mut commas = Vec::new();
+        let mut close = None;
+        let mut inner_quotes = Quotes::default();
+        for (j, &c) in bytes.iter().enumerate().skip(i + 1) {
+            let quoted = inner_quotes.any();
+            inner_quotes.update(c as char);
+            match c {
+                _ if quoted => {}
+                b'{' => depth += 1,
+                b'}' if depth == 0 => {
+                    close = Some(j);
+                    break;
+                }
+                b'}' => depth -= 1,
+                b',' if depth == 0 => commas.push(j),
+                _ => {}
+            }
+        }
+
+        match close {
+            Some(close) if !commas.is_empty() => return Some((i, close, commas)),
+            _ => i += 1,
+        }
+    }
+
+    None
+}
+
+/// Tracks whether the characters of a command are quoted or escaped
+#[derive(Debug, Default)]
+pub(crate) struct Quotes {
+    single: bool,
+    double: bool,
+    escaped: bool,
+}
+
+impl Quotes {
+    pub(crate) fn any(&self) -> bool {
+        self.single || self.double || self.escaped
+    }
+
+    /// Whether a shell would still expand `$` here
+    pub(crate) fn expands(&self) -> bool {
+        self.double && !self.escaped
+    }
+
+    pub(crate) fn update(&mut self, c: char) {
+        if self.escaped {
+            self.escaped = false;
+            return;
+        }
+        match c {
+            '\'' if !self.double => self.single = !self.single,
+            '"' if !self.single => self.double = !self.double,
+            '\\' if !self.single => self.escaped = true,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comma_sets() {
+        assert_eq!(
+            expand("rm -rf {dist,build,.cache}"),
+            "rm -rf dist build .cache"
+        );
+        assert_eq!(expand("cp file.{txt,bak}"), "cp file.txt file.bak");
+        assert_eq!(expand("echo a{,b}c"), "echo ac abc");
+        assert_eq!(expand("echo {a,b} {c,d}"), "echo a b c d");
+    }
+
+    #[test]
+    fn nested_sets() {
+        assert_eq!(expand("echo {a,b{c,d}}"), "echo a bc bd");
+        assert_eq!(expand("echo x{1,2}{y,z}"), "echo x1y x1z x2y x2z");
+    }
+
+    #[test]
+    fn left_alone() {
+        let unchanged = [
+            "echo {{dir}} {{matrix.name}}",
+            "echo ${HOME} ${a,b}",
+            "echo '{a,b}' \"{c,d}\" \\{e,f}",
+            "echo {single} {} {unclosed,",
+            "find . -exec rm {} +",
+        ];
+        for command in unchanged {
+            assert_eq!(expand(command), command);
+        }
+    }
+
+    #[test]
+    fn whitespace() {
+        assert_eq!(expand("echo 'a b'{1,2}\n  ls"), "echo 'a b'1 'a b'2\n  ls");
+    }
+}
@@ -0,0 +1,193 @@
+//! Collapsible log sections for CI providers
+
+use std::fmt::Write as _;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fmt};
+
+use crate::{AbsoluteTaskName, Separator};
+
+/// Marker style for folding each requested task's output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CiGroups {
+    Github,
+    Gitlab,
+}
+
+impl FromStr for CiGroups {
+    type Err = UnknownCiProvider;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "github" => Ok(Self::Github),
+            "gitlab" => Ok(Self::Gitlab),
+            _ => Err(UnknownCiProvider(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownCiProvider(String);
+
+impl fmt::Display for UnknownCiProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown CI provider `{}`, expected github or gitlab",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnknownCiProvider {}
+
+impl CiGroups {
+    /// The provider wrun is running under, if it supports groups
+    pub fn detect() -> Option<Self> {
+        let set = |key| env::var_os(key).is_some_and(|value| !value.is_empty());
+        if set("GITHUB_ACTIONS") {
+            Some(Self::Github)
+        } else if set("GITLAB_CI") {
+            Some(Self::Gitlab)
+        } else {
+            None
+        }
+    }
+
+    fn start(self, name: &AbsoluteTaskName, separator: Separator, out: &mut String) {
+        let section = section_name(name, separator);
+        let name = name.display(separator);
+        match self {
+            Self::Github => writeln!(out, "::group::{name}").unwrap(),
+            Self::Gitlab => writeln!(
+                out,
+                "\x1b[0Ksection_start:{}:{}[collapsed=true]\r\x1b[0K{name}",
+                timestamp(),
+                section,
+            )
+            .unwrap(),
+        }
+    }
+
+    fn end(self, name: &AbsoluteTaskName, separator: Separator, out: &mut String) {
+        match self {
+            Self::Github => out.push_str("::endgroup::\n"),
+            Self::Gitlab => writeln!(
+                out,
+                "\x1b[0Ksection_end:{}:{}\r\x1b[0K",
+                timestamp(),
+                section_name(name, separator),
+            )
+            .unwrap(),
+        }
+    }
+}
+
+/// Tracks which group is open while running a [`Plan`](crate::Plan)
+#[derive(Debug)]
+pub struct Grouper {
+    style: CiGroups,
+    /// For the group names, from
+    /// [`Context::separator`](crate::Context::separator)
+    separator: Separator,
+    current: Option<AbsoluteTaskName>,
+}
+
+impl Grouper {
+    pub fn new(style: CiGroups, separator: Separator) -> Self {
+        Self {
+            style,
+            separator,
+            current: None,
+        }
+    }
+
+    /// Markers to print before running a command from `group`, closing the
+    /// previous group if it is different
+    pub fn enter(&mut self, group: &AbsoluteTaskName) -> String {
+        let mut out = String::new();
+        if self.current.as_ref() == Some(group) {
+            return out;
+        }
+
+        if let Some(previous) = self.current.take() {
+            self.style.end(&previous, self.separator, &mut out);
+        }
+        self.style.start(group, self.separator, &mut out);
+        self.current = Some(group.clone());
+        out
+    }
+
+    /// Markers closing the open group, if any
+    pub fn finish(&mut self) -> String {
+        let mut out = String::new();
+        if let Some(current) = self.current.take() {
+            self.style.end(&current, self.separator, &mut out);
+        }
+        out
+    }
+}
+
+fn timestamp() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH);
+    now.map_or(0, |now| now.as_secs())
+}
+
+/// GitLab only allows letters, digits, `_`, `.`, and `-`
+fn section_name(name: &AbsoluteTaskName, separator: Separator) -> String {
+    let name = name.display(separator).to_string();
+    let name = name.trim_start_matches('/');
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "_.-".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, TaskName};
+
+    #[test]
+    fn github_groups() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            tasks.lint.run = ["echo clippy", { task = "fmt" }]
+            tasks.fmt.run = "echo fmt"
+            tasks.test.run = "echo test"
+            "#,
+        )]);
+        let mut plan = context.plan();
+        for task in ["/lint", "/test"] {
+            plan.push(&TaskName::new(task).relative_to("").unwrap())
+                .unwrap();
+        }
+
+        let mut grouper = Grouper::new(CiGroups::Github, Separator::Slash);
+        let mut log = String::new();
+        for entry in plan.entries() {
+            log.push_str(&grouper.enter(entry.group()));
+            log.push_str(entry.command());
+            log.push('\n');
+        }
+        log.push_str(&grouper.finish());
+
+        assert_eq!(
+            log,
+            "::group::/lint\necho clippy\necho fmt\n::endgroup::\n::group::/test\necho \
+             test\n::endgroup::\n"
+        );
+    }
+
+    #[test]
+    fn gitlab_section_names() {
+        let name = TaskName::new("foo/bar:baz").relative_to("").unwrap();
+        assert_eq!(section_name(&name, Separator::Slash), "foo_bar_baz");
+    }
+}
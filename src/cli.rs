@@ -1,6 +1,6 @@
 use std::env;
-use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
 
 use clap::{CommandFactory, Parser, ValueHint};
 use clap_complete::CompletionCandidate;
@@ -14,10 +14,11 @@ pub(crate) fn parse() -> Args {
 static AFTER_SHORT_HELP: &str = "By default, wrun prints the list of local tasks";
 static AFTER_LONG_HELP: &str = "By default, wrun prints the list of local tasks.
 
-Tasks can be specified in 3 ways:
+Tasks can be specified in 4 ways:
 - foo: The task `foo` defined in the current package (a directory with a wrun.toml file)
 - bar/baz: `baz` from the package `bar`
-- /quux: `quux` from the project root";
+- /quux: `quux` from the project root
+- ../bar/baz: `baz` from the package `bar`, relative to the current package";
 
 #[derive(Debug, Parser)]
 #[command(about = env!("CARGO_PKG_DESCRIPTION"))]
@@ -28,8 +29,134 @@ pub struct Args {
     #[clap(value_hint = ValueHint::DirPath)]
     pub(crate) directory: Option<PathBuf>,
 
+    /// Run commands attached to a pseudo-terminal (Unix only)
+    #[clap(long)]
+    pub(crate) pty: bool,
+
+    /// Only show the first and last N lines of each command's output
+    #[clap(long, value_name = "N")]
+    pub(crate) max_output_lines: Option<usize>,
+
+    /// With --max-output-lines, repeat the last N lines of a failed command's
+    /// stderr after the summary
+    #[clap(long, value_name = "N", requires = "max_output_lines")]
+    pub(crate) stderr_tail: Option<usize>,
+
+    /// Stop running tasks after this many seconds in total, killing the
+    /// current command
+    #[clap(long, value_name = "SECONDS")]
+    pub(crate) max_time: Option<u64>,
+
+    /// Only run the first N commands, then stop successfully
+    #[clap(long, value_name = "N")]
+    pub(crate) stop_after: Option<usize>,
+
+    /// Run up to N of the given tasks at once, prefixing each line of output
+    /// with the task it came from
+    #[clap(short, long, value_name = "N", requires = "tasks")]
+    #[clap(conflicts_with_all = ["pty", "max_output_lines", "summary_only"])]
+    pub(crate) jobs: Option<usize>,
+
+    /// Only list public tasks without a description
+    #[clap(long, conflicts_with = "tasks")]
+    pub(crate) undocumented: bool,
+
+    /// List public tasks without a description, and fail if there are any
+    #[clap(long, conflicts_with = "tasks")]
+    pub(crate) require_descriptions: bool,
+
+    /// Run the given tasks in every package that defines them, after the
+    /// packages each one depends on
+    #[clap(long, requires = "tasks")]
+    pub(crate) all_packages: bool,
+
+    /// Hide commands and their output, only showing the output of failed
+    /// commands and a summary of every task
+    #[clap(long, requires = "tasks")]
+    pub(crate) summary_only: bool,
+
+    /// Hide values printed by --explain-env
+    #[clap(long, requires = "explain_env")]
+    pub(crate) mask: bool,
+
+    /// With --explain-env, also include the `env` of this task
+    #[clap(long, value_name = "TASK", requires = "explain_env")]
+    pub(crate) for_task: Option<String>,
+
+    /// Print --version as JSON, along with the target wrun was built for
+    #[clap(long, requires = "version")]
+    pub(crate) json: bool,
+
+    /// Order to list tasks in
+    #[clap(long, value_enum, default_value_t, conflicts_with = "tasks")]
+    pub(crate) sort: Sort,
+
+    /// Use each task's commands for this profile, where defined
+    #[clap(long, value_name = "NAME")]
+    pub(crate) profile: Option<String>,
+
+    /// Fold the output of each requested task in CI logs (github or gitlab,
+    /// detected if not given)
+    #[clap(long, value_name = "PROVIDER", require_equals = true)]
+    pub(crate) ci_groups: Option<Option<wrun::CiGroups>>,
+
+    /// Print commands to stdout instead of stderr before running them
+    #[clap(long)]
+    pub(crate) echo_stdout: bool,
+
+    /// Set a variable for every command, overriding env files
+    #[clap(short, long, value_name = "KEY=VALUE", value_parser = parse_env)]
+    pub(crate) env: Vec<(String, String)>,
+
+    /// Do not run the project's prelude task first
+    #[clap(long)]
+    pub(crate) no_prelude: bool,
+
+    /// Do not load the project's env files
+    #[clap(long)]
+    pub(crate) no_env_files: bool,
+
+    /// Exit with an error if the given tasks do not run any commands
+    #[clap(long, requires = "tasks")]
+    pub(crate) fail_on_empty: bool,
+
+    /// Print what would run instead of running it
+    #[clap(long, requires = "tasks")]
+    pub(crate) dry_run: bool,
+
+    /// With --dry-run, also list the variables set for each command
+    #[clap(short, long, requires = "dry_run")]
+    pub(crate) verbose: bool,
+
+    /// With --dry-run --verbose, show variable values instead of only names
+    #[clap(long, requires = "verbose")]
+    pub(crate) show_env_values: bool,
+
+    /// Print a hash of everything the given tasks would run instead of
+    /// running them
+    #[clap(long, requires = "tasks")]
+    pub(crate) plan_hash: bool,
+
+    /// Print a shell script that runs what the given tasks would, instead of
+    /// running them
+    #[clap(long, requires = "tasks", conflicts_with_all = ["dry_run", "plan_hash"])]
+    pub(crate) to_script: bool,
+
+    /// List the tasks run by each task beneath it
+    #[clap(long, conflicts_with_all = ["tasks", "version"])]
+    tree: bool,
+
     #[command(flatten)]
     action: ActionArgs,
+
+    /// Read arguments for `{{args}}` from a file, one per line, before any
+    /// given after `--`
+    #[clap(long, value_name = "PATH", requires = "tasks", value_hint = ValueHint::FilePath)]
+    pub(crate) args_file: Option<PathBuf>,
+
+    /// Arguments for `{{args}}` in the given tasks' commands
+    #[clap(last = true, value_name = "ARGS", requires = "tasks")]
+    pub(crate) args: Vec<String>,
 }
 
 #[derive(Debug, clap::Args)]
@@ -43,15 +170,72 @@ struct ActionArgs {
     #[clap(add = ArgValueCompleter::new(TaskCompleter))]
     tasks: Vec<String>,
 
+    /// Choose a task to run from a list, or just list them if not in a
+    /// terminal
+    #[clap(short, long)]
+    interactive: bool,
+
+    /// Exit successfully if a task exists, without running it
+    #[clap(long, value_name = "TASK")]
+    exists: Option<String>,
+
+    /// Open the file defining a task in $EDITOR, or print its location if
+    /// that is not set
+    #[clap(long, value_name = "TASK")]
+    #[clap(add = ArgValueCompleter::new(TaskCompleter))]
+    edit: Option<String>,
+
+    /// Print a Makefile with a target for every task
+    #[clap(long)]
+    dump_makefile: bool,
+
+    /// Print every task and the tasks it runs as JSON
+    #[clap(long)]
+    dump_graph_json: bool,
+
+    /// List the tasks added, removed, or changed in each package since a git
+    /// commit
+    #[clap(long, value_name = "REF")]
+    diff_tasks: Option<String>,
+
+    /// Show where a variable passed to commands is set, from lowest to highest
+    /// precedence, and its final value
+    #[clap(long, value_name = "KEY")]
+    explain_env: Option<String>,
+
     /// Print version
     #[clap(short = 'V', long)]
     version: bool,
+
+    /// Print the task completions offered for PREFIX, for debugging shell
+    /// completion
+    #[clap(long, value_name = "PREFIX", hide = true)]
+    debug_completions: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub(crate) enum Sort {
+    /// As they are defined
+    #[default]
+    File,
+    /// Alphabetically
+    Name,
+    /// Reverse of how they are defined
+    Reverse,
 }
 
 #[derive(Debug)]
 pub(crate) enum Action<'a> {
-    List { all: bool },
+    List { all: bool, tree: bool },
     Run(&'a [String]),
+    Pick,
+    Exists(&'a str),
+    Edit(&'a str),
+    DumpMakefile,
+    DumpGraphJson,
+    DiffTasks(&'a str),
+    ExplainEnv(&'a str),
+    DebugCompletions(&'a str),
     Version,
 }
 
@@ -61,54 +245,183 @@ impl Args {
 
         if action.version {
             Action::Version
+        } else if let Some(task) = &action.exists {
+            Action::Exists(task)
+        } else if action.interactive {
+            Action::Pick
+        } else if action.dump_makefile {
+            Action::DumpMakefile
+        } else if action.dump_graph_json {
+            Action::DumpGraphJson
+        } else if let Some(git_ref) = &action.diff_tasks {
+            Action::DiffTasks(git_ref)
+        } else if let Some(key) = &action.explain_env {
+            Action::ExplainEnv(key)
+        } else if let Some(prefix) = &action.debug_completions {
+            Action::DebugCompletions(prefix)
+        } else if let Some(task) = &action.edit {
+            Action::Edit(task)
         } else if action.all {
-            Action::List { all: true }
+            Action::List {
+                all: true,
+                tree: self.tree,
+            }
         } else if action.tasks.is_empty() {
-            Action::List { all: false }
+            Action::List {
+                all: false,
+                tree: self.tree,
+            }
         } else {
             Action::Run(&action.tasks)
         }
     }
 }
 
+fn parse_env(raw: &str) -> Result<(String, String), String> {
+    match raw.split_once('=') {
+        Some(("", _)) => Err("variable name is empty".to_owned()),
+        Some((key, value)) => Ok((key.to_owned(), value.to_owned())),
+        None => Err(format!("expected KEY=VALUE, found `{raw}`")),
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 struct TaskCompleter;
 
 impl clap_complete::engine::ValueCompleter for TaskCompleter {
     fn complete(&self, current: &OsStr) -> Vec<CompletionCandidate> {
-        let inner = || -> Option<_> {
-            let current = current.to_str()?;
+        let dir = match directory_arg(env::args_os()) {
+            Some(dir) => dir,
+            None => match env::current_dir() {
+                Ok(dir) => dir,
+                Err(_) => return Vec::new(),
+            },
+        };
+        completion_candidates(&dir, current)
+    }
+}
+
+/// The value of `--directory` (or one of its aliases) among the words being
+/// completed, which come after a `--`
+fn directory_arg(args: impl IntoIterator<Item = OsString>) -> Option<PathBuf> {
+    let mut words = args.into_iter().skip_while(|arg| arg != "--").skip(1);
+    let flags = ["--directory", "--cwd", "--dir"];
 
-            let valid_task_char = |c: char| c.is_alphanumeric() || c == '/' || c == ':' || c == '_';
-            if !(current.is_empty() || current.starts_with(valid_task_char)) {
-                return None;
+    let mut directory = None;
+    while let Some(word) = words.next() {
+        let Some(word) = word.to_str() else {
+            continue;
+        };
+        if flags.contains(&word) {
+            directory = words.next().map(PathBuf::from);
+        } else if let Some((flag, value)) = word.split_once('=') {
+            if flags.contains(&flag) {
+                directory = Some(PathBuf::from(value));
             }
+        }
+    }
+    directory.map(|dir| wrun::expand_home(&dir))
+}
 
-            // TODO: Take --directory into account
-            let dir = env::current_dir().ok()?;
-            let context = wrun::Context::from_directory(dir).ok()?;
+/// Every task name offered when completing `current` from `dir`. Shells
+/// filter these down to the ones matching what has been typed so far.
+pub(crate) fn completion_candidates(dir: &Path, current: &OsStr) -> Vec<CompletionCandidate> {
+    let inner = || -> Option<_> {
+        let current = current.to_str()?;
 
-            let help = |task: &wrun::Task| task.description().map(|s| s.to_owned().into());
+        let valid_task_char = |c: char| c.is_alphanumeric() || c == '/' || c == ':' || c == '_';
+        if !(current.is_empty() || current.starts_with(valid_task_char)) {
+            return None;
+        }
 
-            let mut candidates = Vec::new();
-            for (name, task) in context.local_tasks().iter() {
-                candidates.push(CompletionCandidate::new(name).help(help(task)));
-            }
+        let context = wrun::Context::from_directory(dir).ok()?;
+
+        let help = |task: &wrun::Task| task.description().map(|s| s.to_owned().into());
+
+        let mut candidates = Vec::new();
+        for (name, task) in context.local_tasks().iter() {
+            candidates.push(
+                CompletionCandidate::new(name)
+                    .help(help(task))
+                    .hide(task.is_internal()),
+            );
+        }
 
-            let local = context.local_package_name();
-            for (package_name, package) in context.packages() {
-                for (name, task) in package.tasks().iter() {
-                    candidates.push(
-                        CompletionCandidate::new(format!("{package_name}/{name}"))
-                            .help(help(task))
-                            .hide(package_name == local),
-                    );
-                }
+        let local = context.local_package_name();
+        let separator = context.separator();
+        for (package_name, package) in context.packages() {
+            for (name, task) in package.tasks().iter() {
+                candidates.push(
+                    CompletionCandidate::new(format!("{package_name}{separator}{name}"))
+                        .help(help(task))
+                        .hide(package_name == local || task.is_internal()),
+                );
             }
+        }
 
-            Some(candidates)
-        };
+        Some(candidates)
+    };
+
+    inner().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process};
+
+    use super::*;
+
+    #[test]
+    fn candidates() {
+        let root = env::temp_dir().join(format!("wrun-cli-test-{}", process::id()));
+        let files = [
+            (
+                "wrun-project.toml",
+                "packages = [\"app\"]\ntasks.lint = { run = \"true\", description = \"Lint\" }",
+            ),
+            ("app/wrun.toml", "tasks.build.run = \"true\""),
+        ];
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        let candidates = completion_candidates(&root.join("app"), OsStr::new(""));
+        let _ = fs::remove_dir_all(&root);
+
+        let candidates = candidates
+            .iter()
+            .map(|candidate| {
+                (
+                    candidate.get_value().to_str().unwrap(),
+                    candidate.get_help().map(|help| help.to_string()),
+                    candidate.is_hide_set(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(candidates, [
+            ("build", None, false),
+            ("/lint", Some("Lint".to_owned()), false),
+            ("app/build", None, true),
+        ]);
+
+        assert!(completion_candidates(&root, OsStr::new("-")).is_empty());
+    }
+
+    #[test]
+    fn directory_from_words() {
+        let words = |words: &[&str]| directory_arg(words.iter().map(OsString::from));
 
-        inner().unwrap_or_default()
+        assert_eq!(
+            words(&["wrun", "--", "wrun", "--dir", "a", ""]),
+            Some("a".into())
+        );
+        assert_eq!(
+            words(&["wrun", "--", "wrun", "--cwd=b", "x"]),
+            Some("b".into())
+        );
+        assert_eq!(words(&["wrun", "--directory", "a", "--", "wrun"]), None);
+        assert_eq!(words(&["wrun", "--", "wrun", "build"]), None);
     }
 }
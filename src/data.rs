@@ -1,35 +1,274 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::fmt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 
-use crate::VecMap;
+use crate::{RetryPolicy, VecMap};
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct Project {
     #[serde(default)]
-    pub(crate) env_files: Vec<PathBuf>,
+    pub(crate) env_files: Vec<EnvFile>,
+    /// Also load `.env` from the project root and then from the local package,
+    /// after `env_files`, so the most specific file wins
+    #[serde(default)]
+    pub(crate) auto_dotenv: bool,
+    /// Only pass variables from env files and a few essentials like `PATH` to
+    /// commands, rather than wrun's entire environment
+    #[serde(default)]
+    pub(crate) clean_env: bool,
+    /// Variables to keep from wrun's environment with `clean_env`. Entries
+    /// ending in `*` match by prefix.
+    #[serde(default)]
+    pub(crate) env_passthrough: Vec<String>,
+    /// Variables holding lists of paths, which are prepended to instead of
+    /// overwritten
+    #[serde(default = "default_path_vars")]
+    pub(crate) path_vars: Vec<String>,
     #[serde(default)]
     pub(crate) packages: Vec<PathBuf>,
+    /// Task to run before any others in every invocation
+    #[serde(default, deserialize_with = "unsplit_task_name")]
+    pub(crate) prelude: Option<TaskName>,
+    /// Oldest version of wrun that understands this project
+    pub(crate) min_version: Option<String>,
+    /// How to run commands, unless a package or task sets its own
+    #[serde(default)]
+    pub(crate) shell: Shell,
+    /// What goes between the package and task in task names
+    #[serde(default)]
+    pub(crate) separator: Separator,
 
     #[serde(flatten)]
     pub(crate) package: Package,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Default)]
+#[serde(rename_all = "kebab-case", remote = "Self")]
 pub struct Package {
+    /// Take shorthand commands verbatim instead of treating a leading `@` as
+    /// silent
+    #[serde(default, skip_serializing_if = "skip_false")]
+    pub(crate) literal_commands: bool,
+    /// Default for `internal` on the tasks in this file
+    #[serde(default, skip_serializing_if = "skip_false")]
+    pub(crate) internal: bool,
+    /// Package to inherit tasks from, relative to this one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) extends: Option<PathBuf>,
+    /// Packages to run tasks in first with `--all-packages`, relative to this
+    /// one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) depends_on: Vec<PathBuf>,
+    /// Default for `shell` on the tasks in this file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) shell: Option<Shell>,
     #[serde(default)]
     pub(crate) tasks: Tasks,
 }
 
+/// An env file, optionally only loaded in or outside of CI
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub(crate) enum EnvFile {
+    Always(PathBuf),
+    Conditional { path: PathBuf, when: EnvCondition },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum EnvCondition {
+    Ci,
+    Interactive,
+}
+
+impl EnvFile {
+    pub(crate) fn path(&self) -> &Path {
+        match self {
+            Self::Always(path) | Self::Conditional { path, .. } => path,
+        }
+    }
+
+    pub(crate) fn applies(&self, ci: bool) -> bool {
+        match self {
+            Self::Always(_) => true,
+            Self::Conditional { when, .. } => (*when == EnvCondition::Ci) == ci,
+        }
+    }
+}
+
+fn default_path_vars() -> Vec<String> {
+    vec!["PATH".to_owned()]
+}
+
+/// A file that refers to tasks by name
+pub(crate) trait TaskFile: serde::de::DeserializeOwned {
+    /// Split the task names read with the default separator on `separator`
+    /// instead, rejecting any that are invalid
+    fn split_names(&mut self, separator: Separator) -> Result<(), String>;
+}
+
+/// Read a task name without checking it, for [`TaskFile::split_names`]
+fn unsplit_task_name<'de, D>(deserializer: D) -> Result<Option<TaskName>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let raw = Option::<String>::deserialize(deserializer)?;
+    Ok(raw.map(|raw| TaskName::new(&raw)))
+}
+
+// The project file sets its own separator
+impl TaskFile for Project {
+    fn split_names(&mut self, _: Separator) -> Result<(), String> {
+        if let Some(prelude) = &mut self.prelude {
+            *prelude = prelude
+                .resplit(self.separator)
+                .map_err(|error| format!("{error} in `prelude`"))?;
+        }
+        self.package.split_names(self.separator)
+    }
+}
+
+/// Deserialize a project or package file. Task names are split on
+/// `separator` once the rest of the file is parsed. It is `None` for the
+/// project file itself, since that is where it is set.
+pub(crate) fn from_toml<T: TaskFile>(
+    raw: &str,
+    separator: Option<Separator>,
+) -> Result<T, toml::de::Error> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    struct Options {
+        separator: Option<Separator>,
+    }
+
+    let options: Options = toml::from_str(raw)?;
+    let separator = match (separator, options.separator) {
+        (Some(_), Some(_)) => {
+            return Err(serde::de::Error::custom(
+                "`separator` can only be set in the project file",
+            ));
+        }
+        (Some(separator), None) | (None, Some(separator)) => separator,
+        (None, None) => Separator::default(),
+    };
+    let mut parsed: T = toml::from_str(raw)?;
+    parsed
+        .split_names(separator)
+        .map_err(serde::de::Error::custom)?;
+    Ok(parsed)
+}
+
+/// The project's `min-version`, read without parsing anything else
+pub(crate) fn min_version(raw: &str) -> Option<String> {
+    let document = toml::de::DeTable::parse(raw).ok()?;
+    let toml::de::DeValue::String(version) = document.get_ref().get("min-version")?.get_ref()
+    else {
+        return None;
+    };
+    Some(version.clone().into_owned())
+}
+
+/// Line (starting at 1) where `task` is first defined in a package file
+pub(crate) fn task_line(raw: &str, task: &str) -> Option<usize> {
+    task_lines(raw)
+        .into_iter()
+        .find_map(|(name, line)| (name == task).then_some(line))
+}
+
+/// The line each task in `raw` is defined on
+fn task_lines(raw: &str) -> Vec<(String, usize)> {
+    let Ok(document) = toml::de::DeTable::parse(raw) else {
+        return Vec::new();
+    };
+    let Some(toml::de::DeValue::Table(tasks)) =
+        document.get_ref().get("tasks").map(|t| t.get_ref())
+    else {
+        return Vec::new();
+    };
+    tasks
+        .iter()
+        .map(|(key, _)| {
+            let line = raw[..key.span().start].matches('\n').count() + 1;
+            (key.get_ref().to_string(), line)
+        })
+        .collect()
+}
+
 impl Package {
     pub fn tasks(&self) -> &Tasks {
         &self.tasks
     }
+
+    /// Record that the tasks were parsed from `raw`, read from `path`
+    pub(crate) fn set_sources(&mut self, path: &Path, raw: &str) {
+        let lines = task_lines(raw);
+        for (name, task) in self.tasks.0.iter_mut() {
+            let line = lines.iter().find(|(task, _)| task == name);
+            if let (Some((_, line)), Some(task)) = (line, Rc::get_mut(task)) {
+                task.source = Some((path.to_path_buf(), *line));
+            }
+        }
+    }
+
+    /// Serialize back to the format of a `wrun.toml`
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string(self)
+    }
+
+    /// Apply the package-wide options to the tasks just parsed from it
+    fn resolve(&mut self) {
+        for (_, task) in self.tasks.0.iter_mut() {
+            let Some(task) = Rc::get_mut(task) else {
+                continue;
+            };
+            task.package_internal = self.internal;
+            task.package_shell.clone_from(&self.shell);
+            task.run.resolve(self.literal_commands);
+            for runs in task.profiles.values_mut() {
+                runs.resolve(self.literal_commands);
+            }
+        }
+    }
+}
+
+impl TaskFile for Package {
+    fn split_names(&mut self, separator: Separator) -> Result<(), String> {
+        for (name, task) in self.tasks.0.iter_mut() {
+            if let Some(task) = Rc::get_mut(task) {
+                task.split_names(separator)
+                    .map_err(|error| format!("{error} in task `{name}`"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Calls the derived impls, generated as inherent functions by `remote`
+impl<'de> Deserialize<'de> for Package {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let mut package = Package::deserialize(deserializer)?;
+        package.resolve();
+        Ok(package)
+    }
+}
+
+impl Serialize for Package {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        Package::serialize(self, serializer)
+    }
 }
 
 #[serde_as]
@@ -38,25 +277,188 @@ impl Package {
 pub struct Tasks(pub(crate) VecMap<Rc<Task>>);
 
 impl Tasks {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&str, &Task)> {
         self.0
             .iter()
             .map(|(key, task)| -> (&str, &Task) { (key, task) })
     }
+
+    /// Add all of `base`'s tasks that are not already defined
+    pub(crate) fn inherit(&mut self, base: Tasks) {
+        for (name, task) in base.0 {
+            if self.0.get_index(&name).is_none() {
+                self.0.insert(name, task);
+            }
+        }
+    }
 }
 
-#[serde_as]
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Default)]
 pub struct Task {
-    #[serde(default, skip_serializing_if = "skip_false")]
-    internal: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    internal: Option<bool>,
+    /// The package's `internal`, for tasks that do not set their own
+    #[serde(skip)]
+    package_internal: bool,
     #[serde(alias = "desc", skip_serializing_if = "Option::is_none")]
     description: Option<String>,
     #[serde(default)]
-    #[serde_as(as = "serde_with::OneOrMany<_>")]
-    pub(crate) run: Vec<Run>,
+    pub(crate) run: Runs,
+    /// Directories to run everything in, one after another, as a glob
+    /// relative to the package. `{{dir}}` in commands is the current one.
+    #[serde(rename = "for-each", skip_serializing_if = "Option::is_none")]
+    for_each: Option<String>,
+    /// Named sets of variables to run everything with, one after another.
+    /// `{{matrix.name}}` in commands is the current one's name.
+    #[serde(default, skip_serializing_if = "VecMap::is_empty")]
+    matrix: VecMap<BTreeMap<String, String>>,
+    /// Variables to set for the task's commands, over env files and the
+    /// matrix. Values can use inherited or env file variables like
+    /// `${PATH}`.
+    #[serde(default, skip_serializing_if = "VecMap::is_empty")]
+    env: VecMap<String>,
+    /// Variables that must be set for the task to run
+    #[serde(
+        rename = "requires-env",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    requires_env: Vec<String>,
+    /// Replacements for `run` when wrun is invoked with `--profile`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    profiles: BTreeMap<String, Runs>,
+    #[serde(default, skip_serializing_if = "Silent::is_never")]
+    silent: Silent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) retry: Option<RetryPolicy>,
+    /// Run commands attached to a pseudo-terminal (Unix only)
+    #[serde(default, skip_serializing_if = "skip_false")]
+    pty: bool,
+    /// Expand brace sets like `{a,b}` in commands before running them, rather
+    /// than relying on the shell to
+    #[serde(
+        rename = "brace-expansion",
+        default,
+        skip_serializing_if = "skip_false"
+    )]
+    brace_expansion: bool,
+    /// Scheduling priority to run commands with, from -20 (highest) to 19
+    /// (lowest). Unix only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nice: Option<i32>,
+    /// Run every time this task is referenced, rather than only the first
     #[serde(default, skip_serializing_if = "skip_false")]
-    silent: bool,
+    repeatable: bool,
+    /// Why this task should no longer be used, eg what replaces it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deprecated: Option<String>,
+    /// Skip the task unless these hold when it is planned
+    #[serde(skip_serializing_if = "Option::is_none")]
+    when: Option<When>,
+    /// Overrides the package's and project's `shell`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shell: Option<Shell>,
+    /// The package's `shell`, for tasks that do not set their own
+    #[serde(skip)]
+    package_shell: Option<Shell>,
+    /// The file and line the task is defined on, if it was loaded from disk
+    #[serde(skip)]
+    source: Option<(PathBuf, usize)>,
+}
+
+/// How to run commands
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(try_from = "RawShell", into = "RawShell")]
+pub enum Shell {
+    /// The platform's usual shell, from `shell = true` or leaving it unset
+    #[default]
+    Default,
+    /// A program and the arguments to pass it before each command, eg
+    /// `["bash", "-c"]`
+    Program(Vec<String>),
+    /// Split each command into words and run it without a shell, from
+    /// `shell = false`
+    Direct,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(
+    untagged,
+    expecting = "shell must be a list like [\"bash\", \"-c\"], or false"
+)]
+enum RawShell {
+    Program(Vec<String>),
+    Enabled(bool),
+}
+
+impl Shell {
+    /// The program, unless running commands directly
+    pub fn program(&self) -> Option<&str> {
+        match self {
+            Self::Default => Some(Self::default_for(cfg!(windows))[0]),
+            Self::Program(args) => Some(&args[0]),
+            Self::Direct => None,
+        }
+    }
+
+    /// The program and the arguments to pass it before each command, unless
+    /// running commands directly
+    pub fn args(&self) -> Option<Cow<'_, [String]>> {
+        match self {
+            Self::Default => {
+                let args = Self::default_for(cfg!(windows)).map(str::to_owned);
+                Some(Cow::Owned(args.to_vec()))
+            }
+            Self::Program(args) => Some(Cow::Borrowed(args)),
+            Self::Direct => None,
+        }
+    }
+
+    /// `cmd /C` on Windows, since it has no `sh` by default, and `sh -c`
+    /// everywhere else
+    fn default_for(windows: bool) -> [&'static str; 2] {
+        if windows { ["cmd", "/C"] } else { ["sh", "-c"] }
+    }
+}
+
+impl TryFrom<RawShell> for Shell {
+    type Error = &'static str;
+
+    fn try_from(raw: RawShell) -> Result<Self, Self::Error> {
+        match raw {
+            RawShell::Program(args) if args.is_empty() => Err("shell must include a program"),
+            RawShell::Program(args) => Ok(Self::Program(args)),
+            RawShell::Enabled(true) => Ok(Self::Default),
+            RawShell::Enabled(false) => Ok(Self::Direct),
+        }
+    }
+}
+
+impl From<Shell> for RawShell {
+    fn from(shell: Shell) -> Self {
+        match shell {
+            Shell::Default => Self::Enabled(true),
+            Shell::Program(args) => Self::Program(args),
+            Shell::Direct => Self::Enabled(false),
+        }
+    }
+}
+
+/// Requirements for a task to run
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct When {
+    /// Executable that must be on `PATH`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) command: Option<String>,
 }
 
 impl Task {
@@ -65,74 +467,316 @@ impl Task {
     }
 
     pub fn is_internal(&self) -> bool {
-        self.internal
+        self.internal.unwrap_or(self.package_internal)
     }
 
-    pub fn is_silent(&self) -> bool {
+    pub fn silent(&self) -> Silent {
         self.silent
     }
+
+    /// Serialize back to the format of a single `[tasks.*]` table, using the
+    /// shorthand forms for commands where possible
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string(self)
+    }
+
+    pub fn run(&self) -> &[Run] {
+        &self.run.runs
+    }
+
+    /// What to run for `profile`, falling back to [`run`](Self::run) if the
+    /// task does not define it
+    pub fn run_for(&self, profile: Option<&str>) -> &[Run] {
+        profile
+            .and_then(|profile| self.profiles.get(profile))
+            .unwrap_or(&self.run)
+            .runs
+            .as_slice()
+    }
+
+    pub fn retry(&self) -> Option<&RetryPolicy> {
+        self.retry.as_ref()
+    }
+
+    pub fn requires_env(&self) -> &[String] {
+        &self.requires_env
+    }
+
+    pub fn for_each(&self) -> Option<&str> {
+        self.for_each.as_deref()
+    }
+
+    pub fn matrix(&self) -> impl Iterator<Item = (&str, &BTreeMap<String, String>)> {
+        self.matrix.iter()
+    }
+
+    pub fn env(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.env.iter().map(|(key, value)| (key, value.as_str()))
+    }
+
+    pub fn uses_pty(&self) -> bool {
+        self.pty
+    }
+
+    pub fn expands_braces(&self) -> bool {
+        self.brace_expansion
+    }
+
+    pub fn nice(&self) -> Option<i32> {
+        self.nice
+    }
+
+    pub fn is_repeatable(&self) -> bool {
+        self.repeatable
+    }
+
+    pub fn deprecated(&self) -> Option<&str> {
+        self.deprecated.as_deref()
+    }
+
+    /// How to run the task's commands, if not the project's default
+    pub fn shell(&self) -> Option<&Shell> {
+        self.shell.as_ref().or(self.package_shell.as_ref())
+    }
+
+    pub(crate) fn when(&self) -> Option<&When> {
+        self.when.as_ref()
+    }
+
+    /// The file and line the task is defined on, if it was loaded from disk
+    pub fn source(&self) -> Option<(&Path, usize)> {
+        self.source
+            .as_ref()
+            .map(|(path, line)| (path.as_path(), *line))
+    }
+}
+
+impl TaskFile for Task {
+    fn split_names(&mut self, separator: Separator) -> Result<(), String> {
+        self.run.split_names(separator)?;
+        for runs in self.profiles.values_mut() {
+            runs.split_names(separator)?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, serde_with::SerializeDisplay, serde_with::DeserializeFromStr)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TaskName {
     Local(String),
     Root(String),
-    Qualified { package: String, task: String },
+    Qualified {
+        package: String,
+        task: String,
+    },
+    /// A package path starting with `./` or `../`, resolved against the
+    /// package it is used from
+    Relative {
+        package: String,
+        task: String,
+    },
+}
+
+/// What goes between the package and task in task names
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum Separator {
+    #[default]
+    #[serde(rename = "/")]
+    Slash,
+    #[serde(rename = ":")]
+    Colon,
+    #[serde(rename = "::")]
+    DoubleColon,
+}
+
+impl Separator {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Slash => "/",
+            Self::Colon => ":",
+            Self::DoubleColon => "::",
+        }
+    }
+}
+
+impl fmt::Display for Separator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 impl TaskName {
+    /// Split `raw` on the default separator, `/`
     pub fn new(raw: &str) -> Self {
-        if let Some((package, task)) = raw.rsplit_once('/') {
+        Self::with_separator(raw, Separator::default())
+    }
+
+    pub fn with_separator(raw: &str, separator: Separator) -> Self {
+        if let Some((package, task)) = raw.rsplit_once(separator.as_str()) {
             let task = task.to_owned();
             if package.is_empty() {
                 Self::Root(task)
             } else {
+                let is_relative = matches!(package.split('/').next(), Some(".." | "."));
                 let package = package.to_owned();
-                Self::Qualified { package, task }
+                if is_relative {
+                    Self::Relative { package, task }
+                } else {
+                    Self::Qualified { package, task }
+                }
             }
         } else {
             Self::Local(raw.to_owned())
         }
     }
 
-    pub fn relative_to(self, package: impl Into<String>) -> AbsoluteTaskName {
-        match self {
-            Self::Local(task) => AbsoluteTaskName::Qualified {
-                package: package.into(),
-                task,
-            },
+    /// Like [`with_separator`](Self::with_separator), but rejects names that
+    /// could never refer to a task
+    pub fn parse(raw: &str, separator: Separator) -> Result<Self, InvalidTaskName> {
+        if raw.is_empty() {
+            return Err(InvalidTaskName::Empty);
+        }
+
+        if let Some(c) = raw.chars().find(|c| c.is_whitespace() || c.is_control()) {
+            return Err(InvalidTaskName::InvalidChar(c));
+        }
+
+        let (package, task) = raw.rsplit_once(separator.as_str()).unwrap_or(("", raw));
+        if task.is_empty() {
+            return Err(InvalidTaskName::MissingTask(separator));
+        }
+        if task == "." || task == ".." {
+            return Err(InvalidTaskName::InvalidTask(task.to_owned()));
+        }
+
+        if !package.is_empty() && package.split('/').any(str::is_empty) {
+            return Err(InvalidTaskName::EmptyPackageComponent);
+        }
+
+        Ok(Self::with_separator(raw, separator))
+    }
+
+    pub fn relative_to(self, package: impl Into<String>) -> anyhow::Result<AbsoluteTaskName> {
+        let absolute = |package: String, task| {
+            if package.is_empty() {
+                AbsoluteTaskName::Root(task)
+            } else {
+                AbsoluteTaskName::Qualified { package, task }
+            }
+        };
+
+        Ok(match self {
+            Self::Local(task) => absolute(package.into(), task),
             Self::Root(task) => AbsoluteTaskName::Root(task),
             Self::Qualified { package, task } => AbsoluteTaskName::Qualified { package, task },
+            Self::Relative {
+                package: relative,
+                task,
+            } => {
+                let base = package.into();
+                let mut components = base
+                    .split('/')
+                    .filter(|c| !c.is_empty())
+                    .collect::<Vec<_>>();
+                for component in relative.split('/') {
+                    match component {
+                        "" | "." => {}
+                        ".." => {
+                            if components.pop().is_none() {
+                                anyhow::bail!(
+                                    "package `{relative}` escapes the project root when used from \
+                                     /{base}"
+                                );
+                            }
+                        }
+                        component => components.push(component),
+                    }
+                }
+
+                absolute(components.join("/"), task)
+            }
+        })
+    }
+
+    /// Split a name read with [`new`](Self::new) on `separator` instead
+    fn resplit(&self, separator: Separator) -> Result<Self, String> {
+        let raw = self.display(Separator::default()).to_string();
+        Self::parse(&raw, separator).map_err(|error| format!("invalid task name `{raw}`: {error}"))
+    }
+
+    /// Show the name as it would be written with `separator`
+    pub fn display(&self, separator: Separator) -> impl fmt::Display + '_ {
+        let (package, task) = match self {
+            Self::Local(task) => (None, task),
+            Self::Root(task) => (Some(""), task),
+            Self::Qualified { package, task } | Self::Relative { package, task } => {
+                (Some(package.as_str()), task)
+            }
+        };
+        DisplayName {
+            package,
+            task,
+            separator,
         }
     }
 }
 
 impl FromStr for TaskName {
-    type Err = Never;
+    type Err = InvalidTaskName;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Self::new(s))
+        Self::parse(s, Separator::default())
     }
 }
 
+/// Uses the default separator, `/`. See [`display`](Self::display) for
+/// others.
 impl fmt::Display for TaskName {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Local(task) => f.write_str(task),
-            Self::Root(task) => {
-                f.write_str("/")?;
-                f.write_str(task)
-            }
-            Self::Qualified { package, task } => {
-                f.write_str(package)?;
-                f.write_str("/")?;
-                f.write_str(task)
-            }
+        self.display(Separator::default()).fmt(f)
+    }
+}
+
+// Within files, task names are written with the project's separator by
+// `Runs` instead
+impl Serialize for TaskName {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(&self.display(Separator::default()))
+    }
+}
+
+impl<'de> Deserialize<'de> for TaskName {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let raw = String::deserialize(deserializer)?;
+        Self::parse(&raw, Separator::default()).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A task name with an explicit separator. `package` is `None` for names
+/// local to the package they are used from.
+struct DisplayName<'a> {
+    package: Option<&'a str>,
+    task: &'a str,
+    separator: Separator,
+}
+
+impl fmt::Display for DisplayName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(package) = self.package {
+            f.write_str(package)?;
+            f.write_str(self.separator.as_str())?;
         }
+        f.write_str(self.task)
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum AbsoluteTaskName {
     Root(String),
     Qualified { package: String, task: String },
@@ -150,34 +794,136 @@ impl AbsoluteTaskName {
         let (Self::Root(task) | Self::Qualified { task, .. }) = &self;
         task
     }
+
+    /// Show the name as it would be written with `separator`
+    pub fn display(&self, separator: Separator) -> impl fmt::Display + '_ {
+        DisplayName {
+            package: Some(self.package()),
+            task: self.task(),
+            separator,
+        }
+    }
 }
 
+/// Uses the default separator, `/`. See [`display`](Self::display) for
+/// others.
 impl fmt::Display for AbsoluteTaskName {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Root(task) => {
-                f.write_str("/")?;
-                f.write_str(task)
-            }
-            Self::Qualified { package, task } => {
-                f.write_str(package)?;
-                f.write_str("/")?;
-                f.write_str(task)
-            }
-        }
+        self.display(Separator::default()).fmt(f)
     }
 }
 
-#[derive(Debug, Serialize, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Run {
     Command {
         command: String,
-        silent: Option<bool>,
+        silent: Option<Silent>,
+        /// Variable to store the command's stdout in, for `{{name}}`
+        /// placeholders in the task's later commands
+        capture: Option<String>,
+        /// Exit codes besides 0 that mean the command succeeded
+        ok_codes: Vec<i32>,
     },
     Task(TaskName),
 }
 
+impl Serialize for Run {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        RunRef {
+            run: self,
+            literal: false,
+            separator: Separator::default(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// A [`Run`] to serialize using the shorthand and separator of its package
+struct RunRef<'a> {
+    run: &'a Run,
+    literal: bool,
+    separator: Separator,
+}
+
+impl Serialize for RunRef<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap;
+
+        match self.run {
+            Run::Command {
+                command,
+                silent,
+                capture,
+                ok_codes,
+            } => {
+                // A leading @ would be misread as (or is already) the silent
+                // shorthand in non-literal packages
+                let literal = self.literal;
+                let plain = capture.is_none() && ok_codes.is_empty();
+                if plain && (literal || !command.starts_with('@')) {
+                    match silent {
+                        None => return serializer.serialize_str(command),
+                        Some(Silent::Always) if !literal => {
+                            return serializer.serialize_str(&format!("@{command}"));
+                        }
+                        _ => {}
+                    }
+                }
+
+                let mut map = serializer.serialize_map(None)?;
+                map.serialize_entry("cmd", command)?;
+                if let Some(silent) = silent {
+                    map.serialize_entry("silent", silent)?;
+                }
+                if let Some(capture) = capture {
+                    map.serialize_entry("capture", capture)?;
+                }
+                if !ok_codes.is_empty() {
+                    map.serialize_entry("ok-codes", ok_codes)?;
+                }
+                map.end()
+            }
+            Run::Task(task) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("task", &task.display(self.separator).to_string())?;
+                map.end()
+            }
+        }
+    }
+}
+
 impl<'de> Deserialize<'de> for Run {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        match RawRun::deserialize(deserializer)?.run {
+            Run::Task(name) => name
+                .resplit(Separator::default())
+                .map(Run::Task)
+                .map_err(serde::de::Error::custom),
+            run => Ok(run),
+        }
+    }
+}
+
+/// A [`Run`] as written, before applying the options of the package it is in.
+/// Task names are split on the default separator until
+/// [`TaskFile::split_names`].
+struct RawRun {
+    run: Run,
+    /// Whether this is a shorthand command that had a leading `@` removed,
+    /// which packages with `literal-commands` keep instead
+    stripped: bool,
+}
+
+impl<'de> Deserialize<'de> for RawRun {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
@@ -187,7 +933,7 @@ impl<'de> Deserialize<'de> for Run {
         struct RunVisitor;
 
         impl<'de> de::Visitor<'de> for RunVisitor {
-            type Value = Run;
+            type Value = RawRun;
 
             fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
                 formatter.write_str("string or map")
@@ -197,15 +943,18 @@ impl<'de> Deserialize<'de> for Run {
             where
                 E: de::Error,
             {
-                let mut command = v;
-                let mut silent = None;
-                if let Some(s) = command.strip_prefix('@') {
-                    command = s;
-                    silent = Some(true);
-                }
+                let (command, stripped) = match v.strip_prefix('@') {
+                    Some(command) => (command, true),
+                    None => (v, false),
+                };
 
-                let command = command.to_owned();
-                Ok(Run::Command { command, silent })
+                let run = Run::Command {
+                    command: command.to_owned(),
+                    silent: stripped.then_some(Silent::Always),
+                    capture: None,
+                    ok_codes: Vec::new(),
+                };
+                Ok(RawRun { run, stripped })
             }
 
             fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
@@ -228,6 +977,8 @@ impl<'de> Deserialize<'de> for Run {
                 let mut variant = Variant::Unknown;
                 let mut command = None;
                 let mut silent = None;
+                let mut capture = None;
+                let mut ok_codes = None;
                 let mut task = None;
 
                 while let Some(key) = map.next_key::<String>()? {
@@ -251,6 +1002,22 @@ impl<'de> Deserialize<'de> for Run {
                                 variant = Variant::Command;
                                 continue;
                             }
+                            "capture" => {
+                                if capture.is_some() {
+                                    return Err(Error::duplicate_field("capture"));
+                                }
+                                capture = Some(map.next_value()?);
+                                variant = Variant::Command;
+                                continue;
+                            }
+                            "ok-codes" => {
+                                if ok_codes.is_some() {
+                                    return Err(Error::duplicate_field("ok-codes"));
+                                }
+                                ok_codes = Some(map.next_value()?);
+                                variant = Variant::Command;
+                                continue;
+                            }
                             _ => {}
                         }
                     }
@@ -259,22 +1026,33 @@ impl<'de> Deserialize<'de> for Run {
                         if task.is_some() {
                             return Err(Error::duplicate_field("task"));
                         }
-                        task = Some(map.next_value()?);
+                        task = Some(map.next_value::<String>()?);
                         variant = Variant::Task;
                         continue;
                     }
 
-                    return Err(Error::unknown_field(key, &["command", "silent", "task"]));
+                    return Err(Error::unknown_field(key, &[
+                        "command", "silent", "capture", "ok-codes", "task",
+                    ]));
                 }
 
-                if let Some(command) = command {
+                let run = if let Some(command) = command {
                     let silent = silent.unwrap_or_default();
-                    Ok(Run::Command { command, silent })
+                    Run::Command {
+                        command,
+                        silent,
+                        capture,
+                        ok_codes: ok_codes.unwrap_or_default(),
+                    }
                 } else if let Some(task) = task {
-                    Ok(Run::Task(task))
+                    Run::Task(TaskName::new(&task))
                 } else {
-                    Err(Error::missing_field("command or task"))
-                }
+                    return Err(Error::missing_field("command or task"));
+                };
+                Ok(RawRun {
+                    run,
+                    stripped: false,
+                })
             }
         }
 
@@ -282,22 +1060,214 @@ impl<'de> Deserialize<'de> for Run {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-#[doc(hidden)]
-pub struct Never {}
+/// One or more [`Run`]s, as in a task's `run`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Runs {
+    runs: Vec<Run>,
+    /// Which of `runs` had a leading `@` removed, until
+    /// [`resolve`](Self::resolve)
+    stripped: Vec<bool>,
+    /// Whether the package has `literal-commands`, for serializing back
+    literal: bool,
+    separator: Separator,
+}
+
+impl Runs {
+    /// Put back the `@`s removed while parsing if the package takes shorthand
+    /// commands literally
+    fn resolve(&mut self, literal: bool) {
+        self.literal = literal;
+        let stripped = std::mem::take(&mut self.stripped);
+        if !literal {
+            return;
+        }
 
-impl fmt::Display for Never {
-    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (run, _) in self.runs.iter_mut().zip(stripped).filter(|(_, s)| *s) {
+            if let Run::Command {
+                command, silent, ..
+            } = run
+            {
+                command.insert(0, '@');
+                *silent = None;
+            }
+        }
+    }
+}
+
+impl From<Vec<Run>> for Runs {
+    fn from(runs: Vec<Run>) -> Self {
+        Self {
+            runs,
+            ..Self::default()
+        }
+    }
+}
+
+// Only what runs matters, not how it was written
+impl PartialEq for Runs {
+    fn eq(&self, other: &Self) -> bool {
+        self.runs == other.runs
+    }
+}
+
+impl Runs {
+    fn split_names(&mut self, separator: Separator) -> Result<(), String> {
+        self.separator = separator;
+        for run in &mut self.runs {
+            if let Run::Task(name) = run {
+                *name = name.resplit(separator)?;
+            }
+        }
         Ok(())
     }
 }
 
+impl Serialize for Runs {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        // A single run is written on its own, like `run = "..."`
+        let (literal, separator) = (self.literal, self.separator);
+        let with_package = |run| RunRef {
+            run,
+            literal,
+            separator,
+        };
+        match self.runs.as_slice() {
+            [run] => with_package(run).serialize(serializer),
+            runs => serializer.collect_seq(runs.iter().map(with_package)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Runs {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let raw: Vec<RawRun> =
+            serde_with::As::<serde_with::OneOrMany<serde_with::Same>>::deserialize(deserializer)?;
+        let (runs, stripped) = raw.into_iter().map(|raw| (raw.run, raw.stripped)).unzip();
+        Ok(Self {
+            runs,
+            stripped,
+            literal: false,
+            separator: Separator::default(),
+        })
+    }
+}
+
+/// Whether to skip printing commands before running them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Silent {
+    Always,
+    #[default]
+    Never,
+    /// Only when running in CI
+    Ci,
+    /// Only when not running in CI
+    Interactive,
+}
+
+impl Silent {
+    pub fn resolve(self, ci: bool) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Ci => ci,
+            Self::Interactive => !ci,
+        }
+    }
+
+    fn is_never(&self) -> bool {
+        *self == Self::Never
+    }
+}
+
+impl From<bool> for Silent {
+    fn from(silent: bool) -> Self {
+        if silent { Self::Always } else { Self::Never }
+    }
+}
+
+impl Serialize for Silent {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Always => serializer.serialize_bool(true),
+            Self::Never => serializer.serialize_bool(false),
+            Self::Ci => serializer.serialize_str("ci"),
+            Self::Interactive => serializer.serialize_str("interactive"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Silent {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de;
+
+        struct SilentVisitor;
+
+        impl de::Visitor<'_> for SilentVisitor {
+            type Value = Silent;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str(r#"a boolean, "ci", or "interactive""#)
+            }
+
+            fn visit_bool<E: de::Error>(self, v: bool) -> Result<Self::Value, E> {
+                Ok(v.into())
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                match v {
+                    "ci" => Ok(Silent::Ci),
+                    "interactive" => Ok(Silent::Interactive),
+                    _ => Err(E::unknown_variant(v, &["ci", "interactive"])),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(SilentVisitor)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidTaskName {
+    Empty,
+    InvalidChar(char),
+    MissingTask(Separator),
+    InvalidTask(String),
+    EmptyPackageComponent,
+}
+
+impl fmt::Display for InvalidTaskName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("task name is empty"),
+            Self::InvalidChar(c) => write!(f, "task name contains invalid character {c:?}"),
+            Self::MissingTask(separator) => {
+                write!(f, "task name is missing after the last `{separator}`")
+            }
+            Self::InvalidTask(task) => write!(f, "`{task}` is not a valid task name"),
+            Self::EmptyPackageComponent => f.write_str("package path contains an empty component"),
+        }
+    }
+}
+
+impl std::error::Error for InvalidTaskName {}
+
 fn skip_false(b: &bool) -> bool {
     !*b
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use super::*;
 
     #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
@@ -308,7 +1278,9 @@ mod tests {
     fn command(command: &str, silent: Option<bool>) -> Run {
         Run::Command {
             command: command.to_owned(),
-            silent,
+            silent: silent.map(Silent::from),
+            capture: None,
+            ok_codes: Vec::new(),
         }
     }
 
@@ -333,11 +1305,116 @@ mod tests {
         };
     }
 
+    #[test]
+    fn tasks_len() {
+        let empty: Package = from_toml("", None).unwrap();
+        assert!(empty.tasks().is_empty());
+        assert_eq!(empty.tasks().len(), 0);
+
+        let tasks: Package = from_toml("tasks.a.run = 'a'\ntasks.b.run = 'b'", None).unwrap();
+        assert!(!tasks.tasks().is_empty());
+        assert_eq!(tasks.tasks().len(), 2);
+    }
+
     #[test]
     fn deep_task_name() {
         toml_eq!(task!("foo/bar" / "baz"), r#"test = "foo/bar/baz""#);
     }
 
+    #[test]
+    fn parse_valid_task_names() {
+        for name in [
+            "build",
+            "/build",
+            "foo/build",
+            "a/b/c:d",
+            "../sibling/task",
+            "x.y-z_w",
+        ] {
+            assert_eq!(
+                TaskName::parse(name, Separator::Slash),
+                Ok(TaskName::new(name)),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_invalid_task_names() {
+        use InvalidTaskName as E;
+
+        assert_eq!(TaskName::parse("", Separator::Slash), Err(E::Empty));
+        assert_eq!(
+            TaskName::parse("foo/", Separator::Slash),
+            Err(E::MissingTask(Separator::Slash))
+        );
+        assert_eq!(
+            TaskName::parse("/", Separator::Slash),
+            Err(E::MissingTask(Separator::Slash))
+        );
+        assert_eq!(
+            TaskName::parse("foo bar", Separator::Slash),
+            Err(E::InvalidChar(' '))
+        );
+        assert_eq!(
+            TaskName::parse("foo\tbar", Separator::Slash),
+            Err(E::InvalidChar('\t'))
+        );
+        assert_eq!(
+            TaskName::parse("foo//bar", Separator::Slash),
+            Err(E::EmptyPackageComponent)
+        );
+        assert_eq!(
+            TaskName::parse("//bar", Separator::Slash),
+            Err(E::EmptyPackageComponent)
+        );
+        assert_eq!(
+            TaskName::parse("/foo/bar", Separator::Slash),
+            Err(E::EmptyPackageComponent)
+        );
+        assert_eq!(
+            TaskName::parse("foo/..", Separator::Slash),
+            Err(E::InvalidTask("..".to_owned()))
+        );
+    }
+
+    #[test]
+    fn invalid_task_reference() {
+        let run = toml::from_str::<Wrapper<Run>>(r#"test = { task = "foo/" }"#);
+        assert!(run.is_err());
+    }
+
+    #[test]
+    fn relative_task_name() {
+        let resolve = |name: &str, from: &str| {
+            TaskName::new(name)
+                .relative_to(from)
+                .map(|name| name.display(Separator::Slash).to_string())
+        };
+
+        assert_eq!(TaskName::new("../sibling/task"), TaskName::Relative {
+            package: "../sibling".to_owned(),
+            task: "task".to_owned(),
+        });
+        assert_eq!(
+            resolve("../sibling/task", "packages/nested").unwrap(),
+            "packages/sibling/task"
+        );
+        assert_eq!(
+            resolve("./child/task", "packages").unwrap(),
+            "packages/child/task"
+        );
+        assert_eq!(resolve("../task", "packages").unwrap(), "/task");
+        assert_eq!(resolve("../../task", "a/b/c").unwrap(), "a/task");
+        assert_eq!(
+            resolve("../a/../b/task", "packages/nested").unwrap(),
+            "packages/b/task"
+        );
+
+        assert!(resolve("../task", "").is_err());
+        assert!(resolve("../../../task", "a/b").is_err());
+    }
+
     #[test]
     fn run_command_shorthand() {
         toml_eq!(command("echo loud", None), r#"test = "echo loud""#);
@@ -347,6 +1424,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn literal_commands() {
+        let run = |package: &Package| package.tasks.0.get("test").unwrap().run().to_vec();
+
+        let literal: Package = toml::from_str(
+            r#"
+            literal-commands = true
+            tasks.test.run = "@scope/bin"
+            tasks.test.profiles.quiet = ["@scope/bin --quiet", { cmd = "@x", silent = true }]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(run(&literal), vec![command("@scope/bin", None)]);
+        let test = literal.tasks.0.get("test").unwrap();
+        assert_eq!(test.run_for(Some("quiet")), [
+            command("@scope/bin --quiet", None),
+            command("@x", Some(true)),
+        ]);
+        assert_eq!(
+            test.to_toml().unwrap(),
+            "run = \"@scope/bin\"\n\n[profiles]\nquiet = [\"@scope/bin --quiet\", { cmd = \"@x\", \
+             silent = true }]\n"
+        );
+
+        let default: Package = from_toml(r#"tasks.test.run = "@scope/bin""#, None).unwrap();
+        assert_eq!(run(&default), vec![command("scope/bin", Some(true))]);
+    }
+
     #[test]
     fn run_command() {
         toml_eq!(command("foo", None), r#"test = { cmd = "foo" }"#);
@@ -377,10 +1482,11 @@ mod tests {
     #[test]
     fn task_run_single() {
         let task = Task {
-            internal: false,
+            internal: None,
             description: None,
-            run: vec![command("echo test", Some(true))],
-            silent: false,
+            run: vec![command("echo test", Some(true))].into(),
+            silent: Silent::Never,
+            ..Task::default()
         };
         toml_eq!(task, r#"test = { run = "@echo test" }"#);
     }
@@ -388,10 +1494,11 @@ mod tests {
     #[test]
     fn task_run_multiple() {
         let task = Task {
-            internal: false,
+            internal: None,
             description: None,
-            run: vec![command("one", None), command("two", None)],
-            silent: false,
+            run: vec![command("one", None), command("two", None)].into(),
+            silent: Silent::Never,
+            ..Task::default()
         };
         toml_eq!(task, r#"test = { run = ["one", "two"] }"#);
     }
@@ -399,14 +1506,16 @@ mod tests {
     #[test]
     fn task_run_others() {
         let task = Task {
-            internal: false,
+            internal: None,
             description: None,
             run: vec![
                 Run::Task(task!("local")),
                 Run::Task(task!(/ "root")),
                 Run::Task(task!("some" / "other")),
-            ],
-            silent: false,
+            ]
+            .into(),
+            silent: Silent::Never,
+            ..Task::default()
         };
         toml_eq!(
             task,
@@ -414,17 +1523,278 @@ mod tests {
         );
     }
 
+    #[test]
+    fn task_retry() {
+        let task = Task {
+            run: vec![command("flaky", None)].into(),
+            retry: Some(RetryPolicy::new(
+                3,
+                Duration::from_millis(250),
+                Duration::from_secs(30),
+            )),
+            ..Task::default()
+        };
+        toml_eq!(
+            task,
+            r#"test = { run = "flaky", retry = { attempts = 3, base-delay = 0.25 } }"#
+        );
+    }
+
+    #[test]
+    fn silent_ci() {
+        toml_eq!(Silent::Ci, r#"test = "ci""#);
+        toml_eq!(Silent::Interactive, r#"test = "interactive""#);
+        toml_eq!(Silent::Always, "test = true");
+        toml_eq!(Silent::Never, "test = false");
+        assert!(toml::from_str::<Wrapper<Silent>>(r#"test = "sometimes""#).is_err());
+
+        assert!(Silent::Ci.resolve(true));
+        assert!(!Silent::Ci.resolve(false));
+        assert!(!Silent::Interactive.resolve(true));
+        assert!(Silent::Interactive.resolve(false));
+    }
+
+    #[test]
+    fn shell() {
+        toml_eq!(
+            Shell::Program(vec!["bash".to_owned(), "-c".to_owned()]),
+            r#"test = ["bash", "-c"]"#
+        );
+        toml_eq!(Shell::Direct, "test = false");
+        toml_eq!(Shell::Default, "test = true");
+
+        let err = |toml| {
+            toml::from_str::<Wrapper<Shell>>(toml)
+                .unwrap_err()
+                .message()
+                .to_owned()
+        };
+        assert_eq!(err("test = []"), "shell must include a program");
+        assert_eq!(
+            err(r#"test = "bash""#),
+            r#"shell must be a list like ["bash", "-c"], or false"#
+        );
+    }
+
+    #[test]
+    fn package_shell() {
+        let package: Package = toml::from_str(
+            r#"
+            shell = false
+            tasks.direct.run = "true"
+            tasks.bash = { run = "true", shell = ["bash", "-c"] }
+            "#,
+        )
+        .unwrap();
+        let shell = |name| package.tasks.0.get(name).unwrap().shell().cloned();
+        assert_eq!(shell("direct"), Some(Shell::Direct));
+        assert_eq!(
+            shell("bash"),
+            Some(Shell::Program(vec!["bash".to_owned(), "-c".to_owned()]))
+        );
+
+        let package: Package = toml::from_str(r#"tasks.test.run = "true""#).unwrap();
+        assert_eq!(package.tasks.0.get("test").unwrap().shell(), None);
+    }
+
+    #[test]
+    fn default_shell() {
+        assert_eq!(Shell::default_for(true), ["cmd", "/C"]);
+        assert_eq!(Shell::default_for(false), ["sh", "-c"]);
+
+        let args = if cfg!(windows) {
+            ["cmd", "/C"]
+        } else {
+            ["sh", "-c"]
+        };
+        assert_eq!(Shell::default(), Shell::Default);
+        assert_eq!(Shell::Default.args().unwrap().as_ref(), args);
+        assert_eq!(Shell::Default.program(), Some(args[0]));
+        assert_eq!(Shell::Direct.args(), None);
+    }
+
+    #[test]
+    fn task_to_toml() {
+        let roundtrip = |toml: &str| {
+            let task: Task = from_toml(toml, None).unwrap();
+            let serialized = task.to_toml().unwrap();
+            assert_eq!(task, from_toml(&serialized, None).unwrap());
+            serialized
+        };
+
+        assert_eq!(roundtrip(r#"run = "@echo hi""#), "run = \"@echo hi\"\n");
+        assert_eq!(roundtrip(r#"run = "echo hi""#), "run = \"echo hi\"\n");
+        assert_eq!(
+            roundtrip(r#"run = [{ cmd = "@at", silent = true }, { task = "/x" }]"#),
+            "[[run]]\ncmd = \"@at\"\nsilent = true\n\n[[run]]\ntask = \"/x\"\n"
+        );
+        assert_eq!(
+            roundtrip(r#"run = { cmd = "ci only", silent = "ci" }"#),
+            "[run]\ncmd = \"ci only\"\nsilent = \"ci\"\n"
+        );
+    }
+
+    #[test]
+    fn literal_package_to_toml() {
+        let toml = "literal-commands = true\n\n[tasks.test]\nrun = [\"@scope/bin\", { cmd = \
+                    \"quiet\", silent = true }]\n";
+        let package: Package = from_toml(toml, None).unwrap();
+        assert_eq!(package.to_toml().unwrap(), toml);
+    }
+
     #[test]
     fn silent_task() {
         let task = Task {
-            internal: false,
+            internal: None,
             description: None,
-            run: vec![command("echo foo", Some(true)), command("mkdir bar", None)],
-            silent: true,
+            run: vec![command("echo foo", Some(true)), command("mkdir bar", None)].into(),
+            silent: Silent::Always,
+            ..Task::default()
         };
         toml_eq!(
             task,
             r#"test = { run = ["@echo foo", "mkdir bar"], silent = true }"#
         );
     }
+
+    #[test]
+    fn task_lines() {
+        let raw = r#"
+tasks.lint.run = "true"
+
+[tasks.build]
+run = "true"
+"#;
+        assert_eq!(task_line(raw, "lint"), Some(2));
+        assert_eq!(task_line(raw, "build"), Some(4));
+        assert_eq!(task_line(raw, "missing"), None);
+
+        let raw = "[tasks]\nlint.run = \"true\"\ntest = { run = \"true\" }";
+        assert_eq!(task_line(raw, "test"), Some(3));
+    }
+
+    #[test]
+    fn internal_package() {
+        let package: Package = toml::from_str(
+            r#"
+            internal = true
+            tasks.helper.run = "true"
+            tasks.public = { run = "true", internal = false }
+            "#,
+        )
+        .unwrap();
+        let internal = package
+            .tasks
+            .iter()
+            .map(|(name, task)| (name, task.is_internal()))
+            .collect::<Vec<_>>();
+        assert_eq!(internal, [("helper", true), ("public", false)]);
+
+        let package: Package = from_toml(r#"tasks.helper.run = "true""#, None).unwrap();
+        assert!(!package.tasks.0.get("helper").unwrap().is_internal());
+    }
+
+    #[test]
+    fn custom_separator() {
+        let separator = Separator::DoubleColon;
+        let project: Project = from_toml(
+            r#"
+            separator = "::"
+            tasks.all.run = [{ task = "::lint" }, { task = "foo/bar::build" }]
+            "#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(project.separator, separator);
+
+        let run = project.package.tasks.0.get("all").unwrap().run();
+        assert_eq!(run, [
+            Run::Task(TaskName::Root("lint".to_owned())),
+            Run::Task(TaskName::Qualified {
+                package: "foo/bar".to_owned(),
+                task: "build".to_owned(),
+            }),
+        ]);
+
+        for raw in ["build", "::build", "foo/bar::build", "../foo::build"] {
+            let name = TaskName::parse(raw, separator).unwrap();
+            assert_eq!(name.display(separator).to_string(), raw);
+        }
+        let name = TaskName::with_separator("../bar::baz", separator)
+            .relative_to("foo/qux")
+            .unwrap();
+        assert_eq!(name.display(separator).to_string(), "foo/bar::baz");
+        assert_eq!(name.to_string(), "foo/bar/baz");
+        let missing = TaskName::parse("foo::", separator).unwrap_err();
+        assert_eq!(
+            missing.to_string(),
+            "task name is missing after the last `::`"
+        );
+
+        let colon = Separator::Colon;
+        let name = TaskName::parse("foo:bar", colon).unwrap();
+        assert_eq!(name.display(colon).to_string(), "foo:bar");
+    }
+
+    #[test]
+    fn separator_to_toml() {
+        let separator = Some(Separator::DoubleColon);
+        let toml = "[[tasks.all.run]]\ntask = \"pkg::build\"\n\n[[tasks.all.run]]\ntask = \
+                    \"::lint\"\n\n[tasks.all.profiles.release]\ntask = \"../other::build\"\n";
+        let package: Package = from_toml(toml, separator).unwrap();
+        assert_eq!(package.to_toml().unwrap(), toml);
+        let reparsed: Package = from_toml(&package.to_toml().unwrap(), separator).unwrap();
+        assert_eq!(reparsed.tasks.0.get("all"), package.tasks.0.get("all"));
+
+        let task = package.tasks.0.get("all").unwrap();
+        assert_eq!(task.run_for(Some("release")), [Run::Task(
+            TaskName::Relative {
+                package: "../other".to_owned(),
+                task: "build".to_owned(),
+            }
+        )]);
+        assert!(task.to_toml().unwrap().contains("task = \"pkg::build\"\n"));
+
+        let project: Project =
+            from_toml("separator = \"::\"\nprelude = \"pkg::setup\"", None).unwrap();
+        assert_eq!(
+            project.prelude,
+            Some(TaskName::Qualified {
+                package: "pkg".to_owned(),
+                task: "setup".to_owned(),
+            })
+        );
+        let err = from_toml::<Package>(r#"tasks.all.run = { task = "pkg::" }"#, separator);
+        assert_eq!(
+            err.unwrap_err().message(),
+            "invalid task name `pkg::`: task name is missing after the last `::` in task `all`"
+        );
+    }
+
+    #[test]
+    fn separator_only_in_project_file() {
+        let tasks = |raw, separator| {
+            let package: Package = from_toml(raw, Some(separator)).unwrap();
+            package.tasks.0.get("all").unwrap().run().to_vec()
+        };
+
+        let raw = r#"tasks.all.run = [{ task = "foo::build" }]"#;
+        assert_eq!(tasks(raw, Separator::DoubleColon), [Run::Task(
+            TaskName::Qualified {
+                package: "foo".to_owned(),
+                task: "build".to_owned(),
+            }
+        )]);
+        // Nothing carries over from the last file parsed
+        assert_eq!(tasks(raw, Separator::Slash), [Run::Task(TaskName::Local(
+            "foo::build".to_owned()
+        ))]);
+
+        let err = from_toml::<Package>(r#"separator = ":""#, Some(Separator::Slash)).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("`separator` can only be set in the project file"),
+            "{err}"
+        );
+    }
 }
@@ -0,0 +1,286 @@
+//! Comparing a project's tasks against an earlier commit
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context as _, bail};
+
+use crate::data::{self, Package, Project, Separator};
+use crate::{Context, PACKAGE_FILE, PROJECT_FILE, TASK_FILES_DIR, Tasks};
+
+/// How a package's tasks differ from an earlier version of it
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TaskDiff {
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<String>,
+}
+
+impl TaskDiff {
+    pub fn added(&self) -> &[String] {
+        &self.added
+    }
+
+    pub fn removed(&self) -> &[String] {
+        &self.removed
+    }
+
+    /// Tasks defined in both, but differently
+    pub fn changed(&self) -> &[String] {
+        &self.changed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Context {
+    /// Compare the tasks each package defines against those at `git_ref`,
+    /// for every package listed either now or then. Only tasks defined in a
+    /// package's own files are compared, not those inherited with `extends`.
+    /// Packages without any differences are left out.
+    pub fn diff_tasks(&self, git_ref: &str) -> anyhow::Result<Vec<(String, TaskDiff)>> {
+        // Otherwise a typo would look like every file was missing before
+        let output = Command::new("git")
+            .args(["rev-parse", "--verify", "--end-of-options"])
+            .arg(format!("{git_ref}^{{commit}}"))
+            .current_dir(&self.root)
+            .output()
+            .context("running git")?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!("invalid git ref `{git_ref}`: {}", stderr.trim());
+        }
+
+        let old = Source::Git {
+            root: &self.root,
+            git_ref,
+        };
+        let new = Source::WorkTree(&self.root);
+
+        let old_project: Option<Project> = old.parse(Path::new(PROJECT_FILE), None)?;
+        let new_project: Project = new
+            .parse(Path::new(PROJECT_FILE), None)?
+            .context("missing project file")?;
+        // Each revision's packages are parsed with its own separator
+        let old_separator = old_project
+            .as_ref()
+            .map_or_else(Separator::default, |project| project.separator);
+
+        let mut packages = vec![PathBuf::new()];
+        let old_packages = old_project.iter().flat_map(|project| &project.packages);
+        for package in new_project.packages.iter().chain(old_packages) {
+            if !packages.contains(package) {
+                packages.push(package.clone());
+            }
+        }
+
+        let mut diffs = Vec::new();
+        for package in packages {
+            let old_tasks = old.tasks(&package, old_separator)?.unwrap_or_default();
+            let new_tasks = new
+                .tasks(&package, new_project.separator)?
+                .unwrap_or_default();
+            let diff = diff(&old_tasks, &new_tasks);
+            if !diff.is_empty() {
+                diffs.push((package.to_string_lossy().into_owned(), diff));
+            }
+        }
+        Ok(diffs)
+    }
+}
+
+/// Where to read package files from
+enum Source<'a> {
+    WorkTree(&'a Path),
+    Git { root: &'a Path, git_ref: &'a str },
+}
+
+impl Source<'_> {
+    /// The contents of `path`, or `None` if it does not exist. The ref of a
+    /// `Git` source must already be known to be valid.
+    fn read(&self, path: &Path) -> anyhow::Result<Option<String>> {
+        match self {
+            Self::WorkTree(root) => match std::fs::read_to_string(root.join(path)) {
+                Ok(raw) => Ok(Some(raw)),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err.into()),
+            },
+            Self::Git { root, git_ref } => {
+                let spec = format!("{git_ref}:./{}", path.display());
+                let output = Command::new("git")
+                    .args(["show", &spec])
+                    .current_dir(root)
+                    .output()
+                    .context("running git")?;
+                Ok(output
+                    .status
+                    .success()
+                    .then(|| String::from_utf8_lossy(&output.stdout).into_owned()))
+            }
+        }
+    }
+
+    /// The `*.toml` files in `dir`, in lexical order
+    fn task_files(&self, dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        match self {
+            Self::WorkTree(root) => Ok(crate::task_files(&root.join(dir))?
+                .into_iter()
+                .map(|path| dir.join(TASK_FILES_DIR).join(path.file_name().unwrap()))
+                .collect()),
+            Self::Git { root, git_ref } => {
+                let dir = dir.join(TASK_FILES_DIR);
+                let spec = format!("{git_ref}:./{}", dir.display());
+                let output = Command::new("git")
+                    .args(["ls-tree", "--name-only", &spec])
+                    .current_dir(root)
+                    .output()
+                    .context("running git")?;
+                if !output.status.success() {
+                    return Ok(Vec::new());
+                }
+
+                let mut files = String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .filter(|name| name.ends_with(".toml"))
+                    .map(|name| dir.join(name))
+                    .collect::<Vec<_>>();
+                files.sort();
+                Ok(files)
+            }
+        }
+    }
+
+    fn parse<T: data::TaskFile>(
+        &self,
+        path: &Path,
+        separator: Option<Separator>,
+    ) -> anyhow::Result<Option<T>> {
+        let Some(raw) = self.read(path)? else {
+            return Ok(None);
+        };
+        let parsed = data::from_toml(&raw, separator)
+            .with_context(|| format!("parsing {}", self.name(path)))?;
+        Ok(Some(parsed))
+    }
+
+    /// The tasks defined by the package in `dir`, if it exists
+    fn tasks(&self, dir: &Path, separator: Separator) -> anyhow::Result<Option<Tasks>> {
+        let (file, file_separator) = if dir.as_os_str().is_empty() {
+            (PROJECT_FILE, None)
+        } else {
+            (PACKAGE_FILE, Some(separator))
+        };
+        let Some(mut package) = self.parse::<Package>(&dir.join(file), file_separator)? else {
+            return Ok(None);
+        };
+
+        for path in self.task_files(dir)? {
+            let extra: Package = self.parse(&path, Some(separator))?.unwrap_or_default();
+            for (name, task) in extra.tasks.0 {
+                if package.tasks.0.get_index(&name).is_some() {
+                    bail!("task `{name}` from {} is already defined", self.name(&path));
+                }
+                package.tasks.0.insert(name, task);
+            }
+        }
+        Ok(Some(package.tasks))
+    }
+
+    fn name(&self, path: &Path) -> String {
+        match self {
+            Self::WorkTree(_) => path.display().to_string(),
+            Self::Git { git_ref, .. } => format!("{git_ref}:{}", path.display()),
+        }
+    }
+}
+
+/// Classify each task in either `old` or `new`, in the order they are defined
+pub(crate) fn diff(old: &Tasks, new: &Tasks) -> TaskDiff {
+    let mut diff = TaskDiff::default();
+    for (name, task) in new.iter() {
+        match old.0.get(name) {
+            None => diff.added.push(name.to_owned()),
+            Some(old) if **old != *task => diff.changed.push(name.to_owned()),
+            Some(_) => {}
+        }
+    }
+    for (name, _) in old.iter() {
+        if new.0.get(name).is_none() {
+            diff.removed.push(name.to_owned());
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tasks(raw: &str) -> Tasks {
+        data::from_toml::<Package>(raw, None).unwrap().tasks
+    }
+
+    #[test]
+    fn classification() {
+        let old = tasks(
+            r#"
+            tasks.build.run = "cargo build"
+            tasks.lint.run = "cargo clippy"
+            tasks.test.run = "cargo test"
+            "#,
+        );
+        let new = tasks(
+            r#"
+            tasks.build.run = "cargo build"
+            tasks.fmt.run = "cargo fmt"
+            tasks.test.run = "cargo nextest run"
+            "#,
+        );
+
+        assert_eq!(diff(&old, &new), TaskDiff {
+            added: vec!["fmt".to_owned()],
+            removed: vec!["lint".to_owned()],
+            changed: vec!["test".to_owned()],
+        });
+        assert!(diff(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn git_refs() {
+        let project = crate::tests::TestProject::new(&[(
+            PROJECT_FILE,
+            "packages = [\"pkg\"]\ntasks.build.run = \"true\"",
+        )]);
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=wrun", "-c", "user.email=wrun@example.com"])
+                .args(args)
+                .current_dir(&project.0)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {args:?}");
+        };
+        git(&["init", "--quiet"]);
+        git(&["add", "."]);
+        git(&["commit", "--quiet", "--message", "initial"]);
+        std::fs::create_dir(project.0.join("pkg")).unwrap();
+        std::fs::write(
+            project.0.join("pkg").join(PACKAGE_FILE),
+            "tasks.new.run = \"true\"",
+        )
+        .unwrap();
+        let context = project.context();
+
+        let diffs = context.diff_tasks("HEAD").unwrap();
+        assert_eq!(diffs, [("pkg".to_owned(), TaskDiff {
+            added: vec!["new".to_owned()],
+            ..TaskDiff::default()
+        })]);
+
+        let err = context.diff_tasks("no-such-ref").unwrap_err().to_string();
+        assert!(err.starts_with("invalid git ref `no-such-ref`: "), "{err}");
+    }
+}
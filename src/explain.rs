@@ -0,0 +1,168 @@
+//! Tracing where an environment variable's value comes from
+
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::{AbsoluteTaskName, Plan};
+
+/// Somewhere a command's environment variable can be set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvSource {
+    /// wrun's own environment
+    Inherited,
+    /// An env file, by its path as written in the project file
+    EnvFile(PathBuf),
+    /// The `env` of a task, by its name
+    Task(String),
+    /// [`Context::set_env`](crate::Context::set_env)
+    Override,
+    /// Variables wrun always sets, like `ROOT`
+    Wrun,
+}
+
+/// Every source that sets a variable, and the value commands end up with
+#[derive(Debug)]
+pub struct EnvExplanation {
+    sources: Vec<(EnvSource, String)>,
+    value: Option<OsString>,
+}
+
+impl EnvExplanation {
+    /// Each source that sets the variable and the value it sets, from lowest
+    /// to highest precedence
+    pub fn sources(&self) -> impl Iterator<Item = (&EnvSource, &str)> {
+        self.sources
+            .iter()
+            .map(|(source, value)| (source, value.as_str()))
+    }
+
+    /// The value commands get, if any. This can differ from every source for
+    /// path-like variables, which are joined with the inherited value.
+    pub fn value(&self) -> Option<&OsStr> {
+        self.value.as_deref()
+    }
+}
+
+impl Plan<'_> {
+    /// Find every source that sets `key` for commands, including `task`'s
+    /// own `env` if given
+    pub fn explain_env(
+        &mut self,
+        key: &str,
+        task: Option<&AbsoluteTaskName>,
+    ) -> anyhow::Result<EnvExplanation> {
+        let task_env = match task {
+            Some(task) => crate::task_env(&*self.context.get_task(task)?),
+            None => Vec::new(),
+        };
+        let context = &*self.context;
+        let mut sources = Vec::new();
+
+        let inherited = std::env::var_os(key).filter(|_| context.inherits(key));
+        if let Some(inherited) = &inherited {
+            let value = inherited.to_string_lossy().into_owned();
+            sources.push((EnvSource::Inherited, value));
+        }
+
+        for (path, vars) in context.env_file_vars()? {
+            let value = vars.into_iter().rev().find(|(name, _)| name == key);
+            if let Some((_, value)) = value {
+                sources.push((EnvSource::EnvFile(path), value));
+            }
+        }
+
+        if let Some(task) = task {
+            if let Some((_, value)) = task_env.iter().find(|(name, _)| name == key) {
+                let task = task.display(context.separator).to_string();
+                sources.push((EnvSource::Task(task), value.clone()));
+            }
+        }
+
+        for (name, value) in &context.env_overrides {
+            if name == key {
+                sources.push((EnvSource::Override, value.clone()));
+            }
+        }
+
+        let value = self.env_with(&[], &task_env)?.remove(key).or(inherited);
+        if matches!(key, "WRUN" | "ROOT") {
+            if let Some(value) = &value {
+                let value = value.to_string_lossy().into_owned();
+                sources.push((EnvSource::Wrun, value));
+            }
+        }
+
+        Ok(EnvExplanation { sources, value })
+    }
+}
+
+impl fmt::Display for EnvSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inherited => f.write_str("inherited environment"),
+            Self::EnvFile(path) => write!(f, "env file {}", path.display()),
+            Self::Task(task) => write!(f, "env of {task}"),
+            Self::Override => f.write_str("--env"),
+            Self::Wrun => f.write_str("set by wrun"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TestProject;
+    use crate::{PROJECT_FILE, TaskName};
+
+    #[test]
+    fn precedence() {
+        let project = TestProject::new(&[
+            (
+                PROJECT_FILE,
+                r#"
+                env-files = [".env", ".env.local"]
+                tasks.a.run = "true"
+                tasks.a.env = { WRUN_TEST_EXPLAINED = "task", OTHER = "${OTHER}-task" }
+                "#,
+            ),
+            (".env", "WRUN_TEST_EXPLAINED=base\nOTHER=base"),
+            (".env.local", "WRUN_TEST_EXPLAINED=local"),
+        ]);
+
+        let mut context = project.context();
+        context.set_env("WRUN_TEST_EXPLAINED", "cli");
+        let mut plan = context.plan();
+
+        let explained = plan.explain_env("WRUN_TEST_EXPLAINED", None).unwrap();
+        assert_eq!(explained.sources().collect::<Vec<_>>(), [
+            (&EnvSource::EnvFile(".env".into()), "base"),
+            (&EnvSource::EnvFile(".env.local".into()), "local"),
+            (&EnvSource::Override, "cli"),
+        ]);
+        assert_eq!(explained.value(), Some(OsStr::new("cli")));
+
+        let explained = plan.explain_env("WRUN_TEST_UNSET", None).unwrap();
+        assert_eq!(explained.sources().count(), 0);
+        assert_eq!(explained.value(), None);
+
+        let task = TaskName::new("/a").relative_to("").unwrap();
+        let explained = plan
+            .explain_env("WRUN_TEST_EXPLAINED", Some(&task))
+            .unwrap();
+        assert_eq!(explained.sources().collect::<Vec<_>>(), [
+            (&EnvSource::EnvFile(".env".into()), "base"),
+            (&EnvSource::EnvFile(".env.local".into()), "local"),
+            (&EnvSource::Task("/a".to_owned()), "task"),
+            (&EnvSource::Override, "cli"),
+        ]);
+        assert_eq!(explained.value(), Some(OsStr::new("cli")));
+
+        let explained = plan.explain_env("OTHER", Some(&task)).unwrap();
+        assert_eq!(explained.sources().collect::<Vec<_>>(), [
+            (&EnvSource::EnvFile(".env".into()), "base"),
+            (&EnvSource::Task("/a".to_owned()), "${OTHER}-task"),
+        ]);
+        assert_eq!(explained.value(), Some(OsStr::new("base-task")));
+    }
+}
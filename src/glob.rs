@@ -0,0 +1,87 @@
+//! Minimal globbing for directories, supporting `*` and `?` within a path
+//! component
+
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// Directories under `base` matching `pattern`, relative to `base` and in
+/// lexical order. Names starting with `.` are only matched by components that
+/// do too.
+pub(crate) fn dirs(base: &Path, pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let mut matches = vec![PathBuf::new()];
+
+    for component in pattern.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        let mut next = Vec::new();
+        for dir in &matches {
+            if !component.contains(['*', '?']) {
+                let path = dir.join(component);
+                if base.join(&path).is_dir() {
+                    next.push(path);
+                }
+                continue;
+            }
+
+            for entry in fs::read_dir(base.join(dir))? {
+                let entry = entry?;
+                let name = entry.file_name();
+                let Some(name) = name.to_str() else {
+                    continue;
+                };
+                if name.starts_with('.') && !component.starts_with('.') {
+                    continue;
+                }
+                if entry.path().is_dir() && matches_component(component, name) {
+                    next.push(dir.join(name));
+                }
+            }
+        }
+        matches = next;
+    }
+
+    matches.retain(|path| !path.as_os_str().is_empty());
+    matches.sort();
+    Ok(matches)
+}
+
+fn matches_component(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+
+    // Classic backtracking wildcard match, only ever revisiting the last `*`
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn components() {
+        assert!(matches_component("*", "anything"));
+        assert!(matches_component("pkg-*", "pkg-a"));
+        assert!(matches_component("*-b", "pkg-b"));
+        assert!(matches_component("a?c", "abc"));
+        assert!(matches_component("*a*b*", "xaybz"));
+        assert!(!matches_component("pkg-*", "other"));
+        assert!(!matches_component("a?c", "ac"));
+        assert!(!matches_component("exact", "exactly"));
+    }
+}
@@ -0,0 +1,137 @@
+//! The whole project's task graph, for other tools to consume
+
+use serde::Serialize;
+
+use crate::data::Run;
+use crate::{Context, TaskName};
+
+/// Every task in the project's packages, and which tasks each one runs.
+/// Serializes to a stable JSON schema, marked with a `version`.
+#[derive(Debug, Serialize)]
+pub struct TaskGraph {
+    version: u32,
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+struct Node {
+    /// Absolute task name, as used in edges
+    id: String,
+    /// Empty for the root package
+    package: String,
+    task: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    internal: bool,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+struct Edge {
+    from: String,
+    to: String,
+}
+
+impl Context {
+    /// Build the graph of every task in the project. Edges can point to tasks
+    /// in packages missing from the project's package list, which have no
+    /// node of their own.
+    pub fn graph(&self) -> anyhow::Result<TaskGraph> {
+        let mut graph = TaskGraph {
+            version: 1,
+            nodes: Vec::new(),
+            edges: Vec::new(),
+        };
+
+        for (package, tasks) in self.packages.iter() {
+            for (task_name, task) in tasks.tasks.iter() {
+                let name = TaskName::Local(task_name.to_owned()).relative_to(package)?;
+
+                graph.nodes.push(Node {
+                    id: name.display(self.separator).to_string(),
+                    package: package.to_owned(),
+                    task: task_name.to_owned(),
+                    description: task.description().map(str::to_owned),
+                    internal: task.is_internal(),
+                });
+
+                for run in task.run_for(self.profile.as_deref()) {
+                    if let Run::Task(child) = run {
+                        let child = child.clone().relative_to(package)?;
+                        let edge = Edge {
+                            from: name.display(self.separator).to_string(),
+                            to: child.display(self.separator).to_string(),
+                        };
+                        if !graph.edges.contains(&edge) {
+                            graph.edges.push(edge);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(graph)
+    }
+}
+
+impl TaskGraph {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn project_graph() {
+        let context = Context::for_test(&[
+            (
+                "",
+                r#"
+                tasks.all.run = [{ task = "lint" }, { task = "pkg/build" }]
+                tasks.all.description = "Everything"
+                tasks.lint.run = "echo lint"
+                "#,
+            ),
+            (
+                "pkg",
+                r#"
+                tasks.build.run = [{ task = "codegen" }, { task = "/lint" }]
+                tasks.codegen = { run = "echo codegen", internal = true }
+                "#,
+            ),
+        ]);
+
+        fn node(id: &str, package: &str, task: &str, internal: bool) -> serde_json::Value {
+            json!({ "id": id, "package": package, "task": task, "internal": internal })
+        }
+
+        let graph = serde_json::to_value(context.graph().unwrap()).unwrap();
+        let edge = |from: &str, to: &str| json!({ "from": from, "to": to });
+
+        let mut all = node("/all", "", "all", false);
+        all["description"] = json!("Everything");
+        assert_eq!(
+            graph,
+            json!({
+                "version": 1,
+                "nodes": [
+                    all,
+                    node("/lint", "", "lint", false),
+                    node("pkg/build", "pkg", "build", false),
+                    node("pkg/codegen", "pkg", "codegen", true),
+                ],
+                "edges": [
+                    edge("/all", "/lint"),
+                    edge("/all", "pkg/build"),
+                    edge("pkg/build", "pkg/codegen"),
+                    edge("pkg/build", "/lint"),
+                ],
+            })
+        );
+    }
+}
@@ -1,27 +1,96 @@
+mod braces;
+mod ci;
 mod data;
+mod diff;
+mod explain;
+mod glob;
+mod graph;
+mod makefile;
+mod output;
+mod parallel;
+#[cfg(unix)]
+mod pty;
+mod retry;
+mod script;
+mod sha256;
+mod summary;
+mod template;
+mod tree;
 mod vec_map;
 
-use std::collections::HashMap;
-use std::fs;
-use std::path::{Path, PathBuf};
-use std::process::{self, Command};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Write as _};
+use std::path::{self, Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::{env, fs, iter, thread};
 
 use anyhow::{Context as _, bail};
 
+pub use self::ci::{CiGroups, Grouper, UnknownCiProvider};
 use self::data::Package;
-pub use self::data::{AbsoluteTaskName, Task, TaskName, Tasks};
+pub use self::data::{
+    AbsoluteTaskName, InvalidTaskName, Run, Separator, Shell, Silent, Task, TaskName, Tasks,
+};
+pub use self::diff::TaskDiff;
+pub use self::explain::{EnvExplanation, EnvSource};
+pub use self::graph::TaskGraph;
+pub use self::retry::RetryPolicy;
+use self::sha256::Sha256;
+pub use self::summary::{SkipReason, Summary, TaskStatus};
+pub use self::tree::TaskTree;
 use self::vec_map::VecMap;
 
 const PROJECT_FILE: &str = "wrun-project.toml";
 const PACKAGE_FILE: &str = "wrun.toml";
+/// Directory of extra task files next to a project or package file
+const TASK_FILES_DIR: &str = "wrun.d";
+
+/// Variables still inherited from wrun's environment with `clean-env` enabled
+const CLEAN_ENV_DEFAULTS: &[&str] = &["PATH", "HOME"];
 
 #[derive(Debug)]
 pub struct Context {
     root: PathBuf,
-    env_files: Vec<PathBuf>,
+    env_files: Vec<data::EnvFile>,
+    auto_dotenv: bool,
+    load_env_files: bool,
+    force_pty: bool,
+    /// For tasks without their own
+    shell: Shell,
+    separator: Separator,
+    max_output_lines: Option<usize>,
+    /// Only show the output of commands that fail
+    quiet: bool,
+    /// How many lines of a failed command's captured stderr to keep
+    stderr_tail_lines: usize,
+    /// Budget for running the whole plan
+    max_time: Option<Duration>,
+    /// Number of plan entries to run before skipping the rest
+    stop_after: Option<usize>,
+    /// How many requested tasks can run at once
+    jobs: usize,
+    profile: Option<String>,
+    /// Whether wrun is running in CI, for resolving [`Silent`]
+    ci: bool,
+    /// For expanding `~` in configured paths
+    home: Option<PathBuf>,
+    clean_env: bool,
+    env_passthrough: Vec<String>,
+    path_vars: Vec<String>,
+    /// Set with `-e`, overriding everything else
+    env_overrides: Vec<(String, String)>,
+    prelude: Option<TaskName>,
     local: Option<String>,
     packages: VecMap<Package>,
+    warnings: Vec<String>,
+    /// Drop the output of commands that goes through wrun, to keep it out of
+    /// the test harness's
+    #[cfg(test)]
+    discard_output: bool,
 }
 
 impl Context {
@@ -45,27 +114,47 @@ impl Context {
             bail!("failed to find project root")
         };
 
-        let data::Project {
-            env_files,
-            packages,
-            package: root_package,
-        } = toml_from_path(&root.join(PROJECT_FILE))?;
-        let mut context = Self {
-            root,
-            env_files,
-            local: None,
-            packages: VecMap::default(),
-        };
-        context.packages.insert(String::new(), root_package);
+        let raw = fs::read_to_string(root.join(PROJECT_FILE))?;
+        // Checked before anything else, since newer options may not parse
+        if let Some(required) = data::min_version(&raw) {
+            check_min_version(&required, env!("CARGO_PKG_VERSION"))?;
+        }
+        let mut project: data::Project = data::from_toml(&raw, None)?;
+        let separator = project.separator;
+        project.package.set_sources(&root.join(PROJECT_FILE), &raw);
+        include_task_files(&root, &mut project.package, separator)?;
+        project.package = inherit(&root, Path::new(""), project.package, separator)?;
+        let (mut context, packages) = Self::new(root, project);
 
         for dir in packages {
             let name = dir.to_string_lossy().into_owned();
             let package = context
                 .load_package(&dir)
                 .with_context(|| format!("loading package from {}", dir.display()))?;
+            if package.tasks.is_empty() {
+                context
+                    .warnings
+                    .push(format!("package {name} does not define any tasks"));
+            }
             context.packages.insert(name, package);
         }
 
+        let root_tasks = &context.packages.get("").unwrap().tasks;
+        for (package_name, package) in context.packages.iter().skip(1) {
+            for (task, _) in package.tasks.iter() {
+                if root_tasks.0.get_index(task).is_some() {
+                    let name = TaskName::Local(task.to_owned()).relative_to(package_name)?;
+                    let root = TaskName::Root(task.to_owned());
+                    let separator = context.separator;
+                    context.warnings.push(format!(
+                        "{} shadows the root task {}",
+                        name.display(separator),
+                        root.display(separator),
+                    ));
+                }
+            }
+        }
+
         if let Some(path) = local_package_dir {
             let relative = path.strip_prefix(&context.root).unwrap();
             let name = relative.to_string_lossy();
@@ -83,8 +172,59 @@ impl Context {
         Ok(context)
     }
 
+    /// Returns the context with only the root package loaded, along with the
+    /// paths of all other packages
+    fn new(root: PathBuf, project: data::Project) -> (Self, Vec<PathBuf>) {
+        let data::Project {
+            env_files,
+            auto_dotenv,
+            clean_env,
+            env_passthrough,
+            path_vars,
+            packages,
+            prelude,
+            min_version: _,
+            shell,
+            separator,
+            package: root_package,
+        } = project;
+
+        let mut context = Self {
+            root,
+            env_files,
+            auto_dotenv,
+            load_env_files: true,
+            force_pty: false,
+            shell,
+            separator,
+            max_output_lines: None,
+            quiet: false,
+            max_time: None,
+            stop_after: None,
+            jobs: 1,
+            stderr_tail_lines: 10,
+            profile: None,
+            ci: detect_ci(),
+            home: home_dir(),
+            clean_env,
+            env_passthrough,
+            path_vars,
+            env_overrides: Vec::new(),
+            prelude,
+            local: None,
+            packages: VecMap::default(),
+            warnings: Vec::new(),
+            #[cfg(test)]
+            discard_output: false,
+        };
+        context.packages.insert(String::new(), root_package);
+
+        (context, packages)
+    }
+
     fn load_package(&self, path: &Path) -> anyhow::Result<Package> {
-        toml_from_path(&self.root.join(path).join(PACKAGE_FILE))
+        let package = read_package(&self.root.join(path), self.separator)?;
+        inherit(&self.root, path, package, self.separator)
     }
 
     fn get_package<'a>(&'a mut self, name: &str) -> anyhow::Result<&'a Package> {
@@ -98,6 +238,84 @@ impl Context {
         Ok(package)
     }
 
+    fn get_task(&mut self, name: &AbsoluteTaskName) -> anyhow::Result<Rc<Task>> {
+        let package = self.get_package(name.package())?;
+        let Some(task) = package.tasks.0.get(name.task()) else {
+            bail!("Cannot find task: {}", name.display(self.separator))
+        };
+        Ok(Rc::clone(task))
+    }
+
+    /// The file defining `name` and the line its definition starts on. For
+    /// inherited tasks, this is the package they were inherited from.
+    pub fn task_source(&self, name: &AbsoluteTaskName) -> anyhow::Result<(PathBuf, usize)> {
+        let mut package = PathBuf::from(name.package());
+        loop {
+            let dir = self.root.join(&package);
+            let file = if package.as_os_str().is_empty() {
+                dir.join(PROJECT_FILE)
+            } else {
+                dir.join(PACKAGE_FILE)
+            };
+            let raw =
+                fs::read_to_string(&file).with_context(|| format!("reading {}", file.display()))?;
+            if let Some(line) = data::task_line(&raw, name.task()) {
+                return Ok((file, line));
+            }
+
+            for extra in task_files(&dir)? {
+                let extra_raw = fs::read_to_string(&extra)?;
+                if let Some(line) = data::task_line(&extra_raw, name.task()) {
+                    return Ok((extra, line));
+                }
+            }
+
+            let separator = (!package.as_os_str().is_empty()).then_some(self.separator);
+            let parsed: Package = data::from_toml(&raw, separator)?;
+            let Some(extends) = parsed.extends else {
+                bail!("Cannot find task: {}", name.display(self.separator))
+            };
+            package = base_package_path(&package, &extends, "extends")?;
+        }
+    }
+
+    /// `task`'s description with `{{package}}` and `{{profile}}` filled in,
+    /// along with warnings about any other placeholders
+    pub fn description(
+        &self,
+        name: &AbsoluteTaskName,
+        task: &Task,
+    ) -> Option<(String, Vec<String>)> {
+        let description = task.description()?;
+        let (rendered, unknown) = template::render(description, |placeholder| match placeholder {
+            "package" => Some(match name.package() {
+                "" => "/",
+                package => package,
+            }),
+            "profile" => Some(self.profile.as_deref().unwrap_or_default()),
+            _ => None,
+        });
+
+        let name = name.display(self.separator);
+        let warnings = unknown
+            .into_iter()
+            .map(|placeholder| {
+                format!("unknown placeholder `{{{{{placeholder}}}}}` in the description of {name}")
+            })
+            .collect();
+        Some((rendered, warnings))
+    }
+
+    /// Whether `name` refers to a task, loading its package if necessary
+    pub fn has_task(&mut self, name: &AbsoluteTaskName) -> bool {
+        self.get_task(name).is_ok()
+    }
+
+    /// What goes between the package and task in task names
+    pub fn separator(&self) -> Separator {
+        self.separator
+    }
+
     pub fn local_package_name(&self) -> &str {
         if let Some(local) = &self.local {
             local
@@ -115,95 +333,928 @@ impl Context {
         self.packages.iter()
     }
 
+    /// Every package's name, ordered so that each comes after the packages
+    /// it `depends-on`, and otherwise in the order they are listed
+    pub fn dependency_order(&self) -> anyhow::Result<Vec<&str>> {
+        fn visit<'a>(
+            context: &'a Context,
+            name: &'a str,
+            stack: &mut Vec<&'a str>,
+            order: &mut Vec<&'a str>,
+        ) -> anyhow::Result<()> {
+            if order.contains(&name) {
+                return Ok(());
+            }
+            if stack.contains(&name) {
+                stack.push(name);
+                let chain = stack
+                    .iter()
+                    .map(|name| format!("/{name}"))
+                    .collect::<Vec<_>>();
+                bail!("package dependency cycle: {}", chain.join(" -> "));
+            }
+
+            stack.push(name);
+            let package = context.packages.get(name).unwrap();
+            for dependency in &package.depends_on {
+                let path = base_package_path(Path::new(name), dependency, "depends-on")?;
+                let path = path.to_string_lossy();
+                let Some(index) = context.packages.get_index(&path) else {
+                    bail!("/{name} depends on /{path}, which is not in the project's packages");
+                };
+                let (dependency, _) = context.packages.iter().nth(index).unwrap();
+                visit(context, dependency, stack, order)?;
+            }
+            stack.pop();
+
+            order.push(name);
+            Ok(())
+        }
+
+        let mut order = Vec::new();
+        for (name, _) in self.packages.iter() {
+            visit(self, name, &mut Vec::new(), &mut order)?;
+        }
+        Ok(order)
+    }
+
+    /// Likely mistakes found while loading the project
+    pub fn warnings(&self) -> impl Iterator<Item = &str> {
+        self.warnings.iter().map(String::as_str)
+    }
+
     pub fn plan(&mut self) -> Plan<'_> {
         Plan::new(self)
     }
 
+    /// Skip loading the project's `env-files` before running commands
+    pub fn disable_env_files(&mut self) {
+        self.load_env_files = false;
+    }
+
+    /// Run every command attached to a pseudo-terminal, as if they all set
+    /// `pty = true`
+    pub fn force_pty(&mut self) {
+        self.force_pty = true;
+    }
+
+    /// Only show the first and last `max` lines each command writes to stdout
+    /// and stderr. Commands run with a pseudo-terminal are not affected.
+    pub fn limit_output(&mut self, max: usize) {
+        self.max_output_lines = Some(max);
+    }
+
+    /// Hold on to everything commands write, only showing it for those that
+    /// fail. Takes precedence over [`limit_output`](Self::limit_output).
+    pub fn quiet(&mut self) {
+        self.quiet = true;
+    }
+
+    /// Keep the last `lines` of a failed command's stderr for its
+    /// [`Summary`], when it is captured by [`limit_output`](Self::limit_output)
+    pub fn keep_stderr_tail(&mut self, lines: usize) {
+        self.stderr_tail_lines = lines;
+    }
+
+    /// Stop executing a plan once it has taken `max`, killing the command that
+    /// is running at the time
+    pub fn limit_time(&mut self, max: Duration) {
+        self.max_time = Some(max);
+    }
+
+    /// Only run the first `commands` entries of a plan, skipping the rest
+    pub fn stop_after(&mut self, commands: usize) {
+        self.stop_after = Some(commands);
+    }
+
+    /// Run up to `jobs` of the requested tasks at the same time, each one's
+    /// commands still in order. Output is prefixed with the task name, and
+    /// [`force_pty`](Self::force_pty), [`quiet`](Self::quiet), and
+    /// [`limit_output`](Self::limit_output) are ignored.
+    pub fn set_jobs(&mut self, jobs: usize) {
+        self.jobs = jobs.max(1);
+    }
+
+    /// Set `key` for every command, taking precedence over env files
+    pub fn set_env(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.env_overrides.push((key.into(), value.into()));
+    }
+
+    /// Use each task's commands for `profile` where it defines them
+    pub fn set_profile(&mut self, profile: impl Into<String>) {
+        self.profile = Some(profile.into());
+    }
+
+    /// Whether commands get `key` from wrun's environment
+    fn inherits(&self, key: &str) -> bool {
+        !self.clean_env
+            || CLEAN_ENV_DEFAULTS.contains(&key)
+            || self
+                .env_passthrough
+                .iter()
+                .any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => key.starts_with(prefix),
+                    None => key == pattern,
+                })
+    }
+
     fn dotenv(&self) -> anyhow::Result<impl Iterator<Item = (String, String)>> {
         let mut env = HashMap::new();
-        for path in &self.env_files {
-            let path = self.root.join(path);
-            if fs::exists(&path)? {
-                for entry in dotenvy::from_path_iter(path)? {
-                    let entry = entry?;
-                    env.insert(entry.0, entry.1);
+        for (_, vars) in self.env_file_vars()? {
+            env.extend(vars);
+        }
+        Ok(env.into_iter())
+    }
+
+    /// The variables in each env file that is loaded, in the order they are
+    /// applied, along with the file's configured path. With `auto-dotenv`,
+    /// the root and local package `.env` files come last if they are not
+    /// already listed.
+    fn env_file_vars(&self) -> anyhow::Result<Vec<(PathBuf, EnvVars)>> {
+        let mut files = Vec::new();
+        if !self.load_env_files {
+            return Ok(files);
+        }
+
+        let mut paths = self
+            .env_files
+            .iter()
+            .filter(|file| file.applies(self.ci))
+            .map(|file| file.path().to_path_buf())
+            .collect::<Vec<_>>();
+        if self.auto_dotenv {
+            let mut discovered = vec![PathBuf::from(".env")];
+            if let Some(local) = self.local.as_deref().filter(|local| !local.is_empty()) {
+                discovered.push(Path::new(local).join(".env"));
+            }
+            for path in discovered {
+                let listed = self.env_files.iter().any(|file| file.path() == path);
+                if !listed {
+                    paths.push(path);
                 }
             }
         }
-        Ok(env.into_iter())
+
+        for path in paths {
+            let full = self.root.join(expand_home_in(&path, self.home.as_deref()));
+            if fs::exists(&full)? {
+                let vars = dotenvy::from_path_iter(full)?.collect::<Result<_, _>>()?;
+                files.push((path, vars));
+            }
+        }
+        Ok(files)
     }
 }
 
+type EnvVars = Vec<(String, String)>;
+
 #[derive(Debug)]
 pub struct Plan<'a> {
     context: &'a mut Context,
     plan: Vec<PlanEntry>,
+    warnings: Vec<String>,
+    /// Tasks currently being expanded, outermost first, to catch cycles
+    expanding: Vec<AbsoluteTaskName>,
+    /// Tasks already in the plan and the group they were added to. They are
+    /// not added again unless they are repeatable.
+    expanded: HashMap<AbsoluteTaskName, AbsoluteTaskName>,
+    /// Groups that cannot start until another one finishes, when running
+    /// tasks in parallel
+    waits: Vec<(AbsoluteTaskName, AbsoluteTaskName)>,
+    /// Group of the pushed prelude, which every other group waits for
+    prelude: Option<AbsoluteTaskName>,
+    /// Quoted arguments for `{{args}}` in requested tasks, from
+    /// [`set_args`](Self::set_args)
+    args: Option<String>,
+    /// Tasks left out because their `when` conditions do not hold
+    skipped: Vec<(AbsoluteTaskName, SkipReason)>,
+    /// Passed to commands as `WRUN`, if it could be found
+    wrun_bin: Option<PathBuf>,
 }
 
 impl<'a> Plan<'a> {
     fn new(context: &'a mut Context) -> Self {
+        Self::with_current_exe(context, env::current_exe())
+    }
+
+    /// Falls back to looking for `wrun` on `PATH` if the path to the running
+    /// executable is not available
+    fn with_current_exe(context: &'a mut Context, current_exe: io::Result<PathBuf>) -> Self {
+        let mut warnings = Vec::new();
+        let wrun_bin = match current_exe {
+            Ok(path) => Some(path),
+            Err(err) => {
+                let name = format!("wrun{}", env::consts::EXE_SUFFIX);
+                let found =
+                    env::var_os("PATH").and_then(|path| search_path(&path, Path::new(&name)));
+                if found.is_none() {
+                    warnings.push(format!(
+                        "cannot find the wrun executable ({err}), so WRUN will not be set"
+                    ));
+                }
+                found
+            }
+        };
+
         Self {
             context,
             plan: Vec::new(),
+            warnings,
+            expanding: Vec::new(),
+            expanded: HashMap::new(),
+            waits: Vec::new(),
+            prelude: None,
+            args: None,
+            skipped: Vec::new(),
+            wrun_bin,
+        }
+    }
+
+    pub fn entries(&self) -> &[PlanEntry] {
+        &self.plan
+    }
+
+    /// Whether there are no commands to run
+    pub fn is_empty(&self) -> bool {
+        self.plan.is_empty()
+    }
+
+    /// Variables set for each command on top of what it inherits from wrun.
+    /// Path-like variables are prepended to the inherited value rather than
+    /// replacing it.
+    pub fn env(&self) -> anyhow::Result<BTreeMap<String, OsString>> {
+        self.env_with(&[], &[])
+    }
+
+    /// [`env`](Self::env) along with the variables set for just `entry`,
+    /// which take precedence over env files
+    pub fn entry_env(&self, entry: &PlanEntry) -> anyhow::Result<BTreeMap<String, OsString>> {
+        self.env_with(&entry.env, &entry.task_env)
+    }
+
+    fn env_with(
+        &self,
+        extra: &[(String, String)],
+        task_env: &[(String, String)],
+    ) -> anyhow::Result<BTreeMap<String, OsString>> {
+        let mut env = self
+            .context
+            .dotenv()?
+            .chain(extra.iter().cloned())
+            .map(|(key, value)| (key, value.into()))
+            .collect::<BTreeMap<_, OsString>>();
+
+        for key in &self.context.path_vars {
+            let Some(value) = env.get_mut(key) else {
+                continue;
+            };
+            let inherited = env::var_os(key).filter(|_| self.context.inherits(key));
+            if let Some(inherited) = inherited.filter(|inherited| !inherited.is_empty()) {
+                let paths = env::split_paths(value).chain(env::split_paths(&inherited));
+                *value = env::join_paths(paths.collect::<Vec<_>>())
+                    .with_context(|| format!("joining {key}"))?;
+            }
+        }
+
+        // After path-like variables are joined, since these can include the
+        // inherited value themselves, eg `${PATH}:./bin`. References are to
+        // the values from before any of these are set, as the order of a
+        // task's variables is not kept.
+        let task_env = task_env
+            .iter()
+            .map(|(key, value)| {
+                let value = expand_vars(value, |name| {
+                    let inherited = || env::var_os(name).filter(|_| self.context.inherits(name));
+                    env.get(name).cloned().or_else(inherited)
+                });
+                (key.clone(), value)
+            })
+            .collect::<Vec<_>>();
+        env.extend(task_env);
+
+        for (key, value) in &self.context.env_overrides {
+            env.insert(key.clone(), value.into());
+        }
+
+        if let Some(wrun_bin) = &self.wrun_bin {
+            env.insert("WRUN".to_owned(), wrun_bin.clone().into());
         }
+        env.insert("ROOT".to_owned(), self.context.root.clone().into());
+        Ok(env)
     }
 
     pub fn push(&mut self, task_name: &AbsoluteTaskName) -> anyhow::Result<()> {
+        // Left over if an earlier push failed partway through
+        self.expanding.clear();
+        if let Some(prelude) = self.prelude.clone() {
+            self.wait_for(task_name, &prelude);
+        }
+        self.expand(task_name, task_name, true)
+    }
+
+    /// Keep `group` from starting before `other` finishes when running tasks
+    /// in parallel
+    fn wait_for(&mut self, group: &AbsoluteTaskName, other: &AbsoluteTaskName) {
+        let wait = (group.clone(), other.clone());
+        if group != other && !self.waits.contains(&wait) {
+            self.waits.push(wait);
+        }
+    }
+
+    /// Push `task` from every package that defines it, in
+    /// [dependency order](Context::dependency_order)
+    pub fn push_in_every_package(&mut self, task: &str) -> anyhow::Result<()> {
+        let names = self
+            .context
+            .dependency_order()?
+            .into_iter()
+            .filter(|package| {
+                let package = self.context.packages.get(package).unwrap();
+                package.tasks.0.get(task).is_some()
+            })
+            .map(|package| TaskName::Local(task.to_owned()).relative_to(package))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if names.is_empty() {
+            bail!("no package defines the task `{task}`");
+        }
+        for (i, name) in names.iter().enumerate() {
+            self.push(name)?;
+            // Packages run one at a time, even in parallel
+            if i > 0 {
+                self.wait_for(name, &names[i - 1]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Push the project's prelude task, unless there isn't one or it is
+    /// already one of the `requested` tasks. The prelude is resolved relative
+    /// to the project root.
+    pub fn push_prelude(&mut self, requested: &[AbsoluteTaskName]) -> anyhow::Result<()> {
+        let Some(prelude) = self.context.prelude.clone() else {
+            return Ok(());
+        };
+
+        let prelude = prelude.relative_to("")?;
+        if requested.contains(&prelude) {
+            return Ok(());
+        }
+        self.push(&prelude)?;
+        self.prelude = Some(prelude);
+        Ok(())
+    }
+
+    /// Problems found while planning that should not stop execution
+    pub fn warnings(&self) -> impl Iterator<Item = &str> {
+        self.warnings.iter().map(String::as_str)
+    }
+
+    /// Tasks that were not added because of their `when` conditions
+    pub fn skipped(&self) -> impl Iterator<Item = (&AbsoluteTaskName, &SkipReason)> {
+        self.skipped.iter().map(|(task, reason)| (task, reason))
+    }
+
+    fn expand(
+        &mut self,
+        task_name: &AbsoluteTaskName,
+        group: &AbsoluteTaskName,
+        requested: bool,
+    ) -> anyhow::Result<()> {
+        if self.expanding.contains(task_name) {
+            let chain = self.expanding.iter().chain([task_name]);
+            let chain = chain
+                .map(|name| name.display(self.context.separator).to_string())
+                .collect::<Vec<_>>();
+            bail!("cyclic task dependency: {}", chain.join(" -> "));
+        }
+
+        let name = task_name.display(self.context.separator);
         let package_name = task_name.package();
-        let package = self.context.get_package(package_name)?;
+        let task = self.context.get_task(task_name)?;
+        if !task.is_repeatable() {
+            if let Some(earlier) = self.expanded.get(task_name).cloned() {
+                self.wait_for(group, &earlier);
+                return Ok(());
+            }
+        }
+
+        if let Some(command) = task.when().and_then(|when| when.command.as_deref()) {
+            if !self.is_on_path(Path::new(command))? {
+                let skipped = (
+                    task_name.clone(),
+                    SkipReason::MissingCommand(command.to_owned()),
+                );
+                if !self.skipped.contains(&skipped) {
+                    self.skipped.push(skipped);
+                }
+                return Ok(());
+            }
+        }
+
+        if requested && self.args.is_some() {
+            let runs = task.run_for(self.context.profile.as_deref());
+            let uses_args = runs.iter().any(|run| {
+                let data::Run::Command { command, .. } = run else {
+                    return false;
+                };
+                template::render(command, |_| None)
+                    .1
+                    .iter()
+                    .any(|name| name == "args")
+            });
+            if !uses_args {
+                bail!("{name} does not use {{{{args}}}}, so it cannot take arguments");
+            }
+        }
+
+        if requested {
+            if let Some(reason) = task.deprecated() {
+                self.warnings
+                    .push(format!("{name} is deprecated: {reason}"));
+            }
+        }
+
+        if cfg!(not(unix)) && task.nice().is_some() {
+            self.warnings.push(format!(
+                "nice is only supported on Unix, so {name} will run at the normal priority"
+            ));
+        }
+
+        let package_dir = self.context.root.join(package_name);
+        let iterations = match task.for_each() {
+            None => vec![(package_dir, None)],
+            Some(pattern) => {
+                let dirs = glob::dirs(&package_dir, pattern)
+                    .with_context(|| format!("expanding for-each of {name}"))?;
+                if dirs.is_empty() {
+                    self.warnings.push(format!(
+                        "for-each pattern `{pattern}` of {name} does not match any directories"
+                    ));
+                }
+                dirs.into_iter()
+                    .map(|dir| (package_dir.join(&dir), Some(dir)))
+                    .collect()
+            }
+        };
 
-        let Some(task) = package.tasks.0.get(task_name.task()) else {
-            bail!("Cannot find task: {task_name}")
+        let matrix = match task.matrix().collect::<Vec<_>>() {
+            matrix if matrix.is_empty() => vec![None],
+            matrix => matrix.into_iter().map(Some).collect(),
         };
-        let task = Rc::clone(task);
 
-        for run in &task.run {
+        // Against the same variables the commands get, for every matrix entry
+        if !task.requires_env().is_empty() {
+            let task_env = task_env(&task);
+            for entry in &matrix {
+                let env = self.env_with(&matrix_env(*entry), &task_env)?;
+                for key in task.requires_env() {
+                    let inherited = self.context.inherits(key) && env::var_os(key).is_some();
+                    if !env.contains_key(key) && !inherited {
+                        bail!("missing required environment variable `{key}` for {name}");
+                    }
+                }
+            }
+        }
+
+        // Runs everything once per directory and matrix entry, in order,
+        // stopping at the first failure like any other command
+        for (directory, dir) in iterations {
+            let dir = dir.as_ref().map(|dir| dir.to_string_lossy());
+            for entry in &matrix {
+                self.expanding.push(task_name.clone());
+                let placeholders = Placeholders {
+                    dir: dir.as_deref(),
+                    matrix: *entry,
+                    args: if requested { self.args.clone() } else { None },
+                };
+                self.expand_runs(task_name, group, &task, &directory, placeholders)?;
+                self.expanding.pop();
+            }
+        }
+
+        self.expanded.insert(task_name.clone(), group.clone());
+        Ok(())
+    }
+
+    /// Use `args`, which were passed after `--`, for `{{args}}` in the
+    /// commands of the tasks pushed after this. Each is shell-quoted.
+    pub fn set_args(&mut self, args: &[String]) -> anyhow::Result<()> {
+        let quoted =
+            shlex::try_join(args.iter().map(String::as_str)).context("quoting arguments")?;
+        self.args = Some(quoted);
+        Ok(())
+    }
+
+    fn expand_runs(
+        &mut self,
+        task_name: &AbsoluteTaskName,
+        group: &AbsoluteTaskName,
+        task: &Task,
+        directory: &Path,
+        placeholders: Placeholders,
+    ) -> anyhow::Result<()> {
+        let Placeholders { dir, matrix, args } = placeholders;
+        let package_name = task_name.package();
+        let env = matrix_env(matrix);
+        let task_env = task_env(task);
+
+        for run in task.run_for(self.context.profile.as_deref()) {
             match run {
-                data::Run::Command { command, silent } => {
+                data::Run::Command {
+                    command,
+                    silent,
+                    capture,
+                    ok_codes,
+                } => {
+                    // Before filling in placeholders, so their values are not
+                    // expanded
+                    let command = if task.expands_braces() {
+                        braces::expand(command)
+                    } else {
+                        command.clone()
+                    };
+                    let lookup = |name: &str| match name {
+                        "dir" => dir,
+                        "matrix.name" => matrix.map(|(name, _)| name),
+                        // Only the requested task gets arguments
+                        "args" => Some(args.as_deref().unwrap_or_default()),
+                        _ => None,
+                    };
+                    let command = template::render(&command, lookup).0;
+                    let shell = task.shell().unwrap_or(&self.context.shell);
+                    if *shell == Shell::Direct {
+                        if let Some(c) = shell_syntax(&command) {
+                            bail!(
+                                "{} runs `{command}` without a shell, so it cannot use `{c}`",
+                                task_name.display(self.context.separator),
+                            );
+                        }
+                    }
                     self.plan.push(PlanEntry {
                         task: task_name.clone(),
-                        directory: self.context.root.join(package_name),
-                        command: command.clone(),
-                        silent: silent.unwrap_or(task.is_silent()),
+                        group: group.clone(),
+                        directory: directory.to_path_buf(),
+                        command,
+                        silent: silent.unwrap_or(task.silent()).resolve(self.context.ci),
+                        retry: task.retry,
+                        pty: task.uses_pty() || self.context.force_pty,
+                        capture: capture.clone(),
+                        ok_codes: ok_codes.clone(),
+                        env: env.clone(),
+                        task_env: task_env.clone(),
+                        shell: shell.clone(),
+                        nice: task.nice(),
+                        source: task.source().map(|(path, line)| (path.to_path_buf(), line)),
                     });
                 }
-                data::Run::Task(task) => self.push(&task.clone().relative_to(package_name))?,
+                data::Run::Task(task) => {
+                    self.expand(&task.clone().relative_to(package_name)?, group, false)?;
+                }
             }
         }
 
         Ok(())
     }
 
-    pub fn execute(self, prerun: impl Fn(&PlanEntry)) -> anyhow::Result<()> {
-        let wrun_bin = std::env::current_exe().expect("path to wrun");
+    /// Stable fingerprint of everything that would run. This covers, in
+    /// order, each command along with its task, its directory relative to the
+    /// project root, its matrix and task variables, its shell, `ok-codes`,
+    /// `capture`, `retry`, `pty`, and `nice`. After that come the variables
+    /// loaded from env files and set with [`Context::set_env`], then
+    /// `clean-env` and `env-passthrough`.
+    ///
+    /// Inherited variables, `WRUN`, and `ROOT` are left out since they differ
+    /// between machines.
+    pub fn hash(&self) -> anyhow::Result<String> {
+        let mut hasher = Sha256::new();
+        let mut field = |value: &str| {
+            hasher.update(&(value.len() as u64).to_be_bytes());
+            hasher.update(value.as_bytes());
+        };
 
+        field("wrun-plan-v2");
+        field(&self.plan.len().to_string());
         for entry in &self.plan {
-            prerun(entry);
+            let directory = entry.directory.strip_prefix(&self.context.root);
+            field(&entry.task.display(Separator::default()).to_string());
+            field(&directory.unwrap_or(&entry.directory).to_string_lossy());
+            field(&entry.command);
+            if !entry.env.is_empty() {
+                field("env");
+                for (key, value) in &entry.env {
+                    field(key);
+                    field(value);
+                }
+            }
+            if !entry.task_env.is_empty() {
+                field("task-env");
+                for (key, value) in &entry.task_env {
+                    field(key);
+                    field(value);
+                }
+            }
+            field("shell");
+            match entry.shell.args() {
+                Some(args) => args.iter().for_each(|arg| field(arg)),
+                None => field("false"),
+            }
+            field("ok-codes");
+            entry
+                .ok_codes
+                .iter()
+                .for_each(|code| field(&code.to_string()));
+            field("capture");
+            field(entry.capture.as_deref().unwrap_or_default());
+            field("retry");
+            field(&serde_json::to_string(&entry.retry)?);
+            field("pty");
+            field(&entry.pty.to_string());
+            field("nice");
+            field(&format!("{:?}", entry.nice));
+        }
+
+        field("env");
+        let env = self.context.dotenv()?.collect::<BTreeMap<_, _>>();
+        for (key, value) in &env {
+            field(key);
+            field(value);
+        }
+
+        // Later overrides of the same variable win
+        field("overrides");
+        let overrides = self
+            .context
+            .env_overrides
+            .iter()
+            .map(|(key, value)| (key, value))
+            .collect::<BTreeMap<_, _>>();
+        for (key, value) in overrides {
+            field(key);
+            field(value);
+        }
 
-            let exit = Command::new("sh")
-                .current_dir(&*entry.directory)
-                .envs(self.context.dotenv()?)
-                .env("WRUN", &wrun_bin)
-                .env("ROOT", &self.context.root)
-                .args(["-c", entry.command()])
-                .status()?;
+        field("clean-env");
+        field(&self.context.clean_env.to_string());
+        for key in &self.context.env_passthrough {
+            field(key);
+        }
+
+        Ok(hasher.finish())
+    }
+
+    pub fn execute(self, prerun: impl Fn(&PlanEntry)) -> anyhow::Result<Summary> {
+        if !self.plan.is_empty() {
+            self.check_shell()?;
+        }
+        if self.context.jobs > 1 {
+            return self.execute_parallel(prerun);
+        }
+
+        let deadline = self.context.max_time.map(|max| Instant::now() + max);
+        let mut summary = self.skipped_summary();
+        let mut failed = false;
+        let mut timed_out = false;
+        // Output of commands with `capture`, for later commands in the same
+        // task. Nothing is stored if the command fails, since the task stops
+        // there anyway.
+        let mut captured = HashMap::<&AbsoluteTaskName, HashMap<String, String>>::new();
+        for (i, entry) in self.plan.iter().enumerate() {
+            if timed_out {
+                let status = TaskStatus::Skipped(SkipReason::TimeLimit);
+                summary.record(&entry.task, status);
+                continue;
+            } else if failed {
+                let status = TaskStatus::Skipped(SkipReason::EarlierFailure);
+                summary.record(&entry.task, status);
+                continue;
+            } else if self.context.stop_after.is_some_and(|max| i >= max) {
+                let status = TaskStatus::Skipped(SkipReason::StoppedEarly);
+                summary.record(&entry.task, status);
+                continue;
+            }
+
+            let task = &entry.task;
+            let rendered;
+            let entry = match captured.get(task) {
+                Some(values) => {
+                    let lookup = |name: &str| values.get(name).map(String::as_str);
+                    rendered = PlanEntry {
+                        command: template::render(&entry.command, lookup).0,
+                        ..entry.clone()
+                    };
+                    &rendered
+                }
+                None => entry,
+            };
+
+            let attempts = entry.retry.map_or(1, |retry| retry.attempts());
+            for attempt in 1..=attempts {
+                if attempt > 1 {
+                    let retry = entry.retry.unwrap();
+                    sleep_until(retry.jittered_delay(attempt - 1), deadline);
+                }
+
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    summary.record(&entry.task, TaskStatus::TimedOut);
+                    timed_out = true;
+                    break;
+                }
+
+                prerun(entry);
+
+                let mut command = self.command(entry)?;
+                // So killing the command at the deadline also stops anything
+                // it started. Only then, since it also takes the command out
+                // of the terminal's foreground, away from Ctrl-C and input.
+                #[cfg(unix)]
+                if deadline.is_some() {
+                    use std::os::unix::process::CommandExt as _;
 
-            if !exit.success() {
-                let code = exit.code().unwrap(); // FIXME
-                process::exit(code)
+                    command.process_group(0);
+                }
+                let result = match &entry.capture {
+                    Some(name) => {
+                        output::run_captured(command, deadline, None)?.map(|(exit, stdout)| {
+                            if entry.succeeded(exit) {
+                                let values = captured.entry(task).or_default();
+                                values.insert(name.clone(), stdout);
+                            }
+                            (exit, Vec::new())
+                        })
+                    }
+                    None => run(entry, command, self.context, deadline)?,
+                };
+                let Some((exit, stderr_tail)) = result else {
+                    summary.record(&entry.task, TaskStatus::TimedOut);
+                    timed_out = true;
+                    break;
+                };
+
+                if entry.succeeded(exit) {
+                    summary.record(&entry.task, TaskStatus::Ran);
+                    break;
+                } else if attempt == attempts {
+                    let code = exit_code(exit);
+                    summary.record(&entry.task, TaskStatus::Failed { code });
+                    summary.record_stderr_tail(&entry.task, stderr_tail);
+                    failed = true;
+                }
             }
         }
 
+        Ok(summary)
+    }
+
+    /// A summary of just the tasks skipped while planning
+    fn skipped_summary(&self) -> Summary {
+        let mut summary = Summary::default();
+        for (task, reason) in &self.skipped {
+            summary.record(task, TaskStatus::Skipped(reason.clone()));
+        }
+        summary
+    }
+
+    /// Fail once up front if the shell does not exist, rather than for every
+    /// command
+    fn check_shell(&self) -> anyhow::Result<()> {
+        let mut checked = Vec::new();
+        for shell in self.plan.iter().filter_map(|entry| entry.shell.program()) {
+            if checked.contains(&shell) {
+                continue;
+            }
+            if !self.is_on_path(Path::new(shell))? {
+                bail!("shell `{shell}` was not found");
+            }
+            checked.push(shell);
+        }
         Ok(())
     }
+
+    /// Whether commands can run `command`, either as a path or by looking it
+    /// up on the `PATH` they get
+    fn is_on_path(&self, command: &Path) -> anyhow::Result<bool> {
+        if command.components().count() > 1 {
+            return Ok(command.is_file());
+        }
+
+        let path = match self.env()?.remove("PATH") {
+            Some(path) => Some(path),
+            None => env::var_os("PATH"),
+        };
+        Ok(path.is_some_and(|path| search_path(&path, command).is_some()))
+    }
+
+    fn command(&self, entry: &PlanEntry) -> anyhow::Result<Command> {
+        let mut command = match entry.shell.args() {
+            Some(shell) => {
+                let mut command = Command::new(&shell[0]);
+                command.args(&shell[1..]);
+                #[cfg(windows)]
+                if is_cmd(&shell[0]) {
+                    use std::os::windows::process::CommandExt as _;
+
+                    // cmd splits up its own command line, which the quoting
+                    // used for other programs would break
+                    command.raw_arg(entry.command());
+                } else {
+                    command.arg(entry.command());
+                }
+                #[cfg(not(windows))]
+                command.arg(entry.command());
+                command
+            }
+            None => {
+                let Some(words) = shlex::split(entry.command()) else {
+                    bail!(
+                        "cannot split `{}` into words to run without a shell",
+                        entry.command()
+                    );
+                };
+                let Some((program, args)) = words.split_first() else {
+                    bail!("cannot run an empty command without a shell");
+                };
+                let mut command = Command::new(program);
+                command.args(args);
+                command
+            }
+        };
+
+        if self.context.clean_env {
+            command.env_clear();
+            for (key, value) in env::vars_os() {
+                if key.to_str().is_some_and(|key| self.context.inherits(key)) {
+                    command.env(key, value);
+                }
+            }
+        }
+
+        #[cfg(unix)]
+        if let Some(nice) = entry.nice {
+            use std::os::unix::process::CommandExt as _;
+
+            // SAFETY: setpriority is async-signal-safe, and nothing else is
+            // done between forking and exec
+            unsafe {
+                command.pre_exec(move || {
+                    if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+
+        command
+            .current_dir(&*entry.directory)
+            .envs(self.entry_env(entry)?);
+        Ok(command)
+    }
 }
 
-#[derive(Debug)]
+/// Variables set by one entry of a task's matrix
+fn matrix_env(matrix: Option<(&str, &BTreeMap<String, String>)>) -> Vec<(String, String)> {
+    matrix.map_or_else(Vec::new, |(_, env)| {
+        env.iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    })
+}
+
+fn task_env(task: &Task) -> Vec<(String, String)> {
+    task.env()
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect()
+}
+
+/// Values for the placeholders in one run through a task's commands
+struct Placeholders<'a> {
+    /// The current `for-each` directory
+    dir: Option<&'a str>,
+    matrix: Option<(&'a str, &'a BTreeMap<String, String>)>,
+    args: Option<String>,
+}
+
+#[derive(Debug, Clone)]
 pub struct PlanEntry {
     task: AbsoluteTaskName,
+    /// The task passed to [`Plan::push`] that this entry came from
+    group: AbsoluteTaskName,
     directory: PathBuf,
     command: String,
     silent: bool,
+    retry: Option<RetryPolicy>,
+    pty: bool,
+    capture: Option<String>,
+    ok_codes: Vec<i32>,
+    /// Set on top of the plan's [`env`](Plan::env), eg from the task's matrix
+    env: Vec<(String, String)>,
+    /// The task's `env`, set after path-like variables are joined, with
+    /// `${NAME}` not yet expanded
+    task_env: Vec<(String, String)>,
+    shell: Shell,
+    nice: Option<i32>,
+    source: Option<(PathBuf, usize)>,
 }
 
 impl PlanEntry {
@@ -211,6 +1262,15 @@ impl PlanEntry {
         &self.task
     }
 
+    /// The requested task this command runs as part of
+    pub fn group(&self) -> &AbsoluteTaskName {
+        &self.group
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
     pub fn command(&self) -> &str {
         &self.command
     }
@@ -218,8 +1278,2035 @@ impl PlanEntry {
     pub fn silent(&self) -> bool {
         self.silent
     }
+
+    /// The file and line of the task this command is from, if it was loaded
+    /// from disk
+    pub fn source(&self) -> Option<(&Path, usize)> {
+        self.source
+            .as_ref()
+            .map(|(path, line)| (path.as_path(), *line))
+    }
+
+    /// Whether `exit` counts as success, either 0 or one of the command's
+    /// `ok-codes`
+    fn succeeded(&self, exit: ExitStatus) -> bool {
+        exit.success() || self.ok_codes.contains(&exit_code(exit))
+    }
 }
 
-fn toml_from_path<T: serde::de::DeserializeOwned>(path: &Path) -> anyhow::Result<T> {
-    Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+/// Run `entry`'s command, along with the end of its stderr if it was captured.
+/// Returns `None` if it was killed for running past `deadline`.
+fn run(
+    entry: &PlanEntry,
+    mut command: Command,
+    context: &Context,
+    deadline: Option<Instant>,
+) -> io::Result<Option<(ExitStatus, Vec<String>)>> {
+    #[cfg(unix)]
+    if entry.pty && context.quiet {
+        let mut output = Vec::new();
+        let status = pty::run(command, &mut output, deadline)?;
+        if !status.is_some_and(|status| status.success()) {
+            io::stdout().write_all(&output)?;
+        }
+        return Ok(status.map(|status| (status, Vec::new())));
+    } else if entry.pty {
+        let status = pty::run(command, &mut io::stdout(), deadline)?;
+        return Ok(status.map(|status| (status, Vec::new())));
+    }
+    #[cfg(not(unix))]
+    let _ = entry;
+
+    if context.quiet {
+        let (stdout, stderr) = (&mut io::stdout(), &mut io::stderr());
+        let status = output::run_quiet(command, deadline, stdout, stderr)?;
+        return Ok(status.map(|status| (status, Vec::new())));
+    }
+
+    if let Some(max) = context.max_output_lines {
+        let keep = context.stderr_tail_lines;
+        #[cfg(test)]
+        if context.discard_output {
+            return output::run_truncated(command, max, keep, deadline, io::sink(), io::sink());
+        }
+        // Not locked for the whole command, since the threads copying its
+        // output are abandoned if they do not finish after killing it
+        return output::run_truncated(command, max, keep, deadline, io::stdout(), io::stderr());
+    }
+
+    let status = wait(&mut command.spawn()?, deadline)?;
+    Ok(status.map(|status| (status, Vec::new())))
+}
+
+/// The first file named `name` in one of the directories in `path`
+fn search_path(path: &OsStr, name: &Path) -> Option<PathBuf> {
+    // Windows finds `cmd` as `cmd.exe`
+    let exe = cfg!(windows) && name.extension().is_none();
+    env::split_paths(path)
+        .flat_map(|dir| {
+            let candidate = dir.join(name);
+            let with_exe = exe.then(|| candidate.with_extension("exe"));
+            iter::once(candidate).chain(with_exe)
+        })
+        .find(|candidate| candidate.is_file())
+}
+
+/// Wait for `child` to exit, killing it if it is still running at `deadline`
+pub(crate) fn wait(child: &mut Child, deadline: Option<Instant>) -> io::Result<Option<ExitStatus>> {
+    wait_or_cancel(child, deadline, None)
+}
+
+/// Like [`wait`], but also kill `child` once `cancel` is set. On Unix, if
+/// `child` leads its own process group, the whole group is killed along with
+/// it so nothing it started keeps running.
+pub(crate) fn wait_or_cancel(
+    child: &mut Child,
+    deadline: Option<Instant>,
+    cancel: Option<&AtomicBool>,
+) -> io::Result<Option<ExitStatus>> {
+    if deadline.is_none() && cancel.is_none() {
+        return child.wait().map(Some);
+    }
+
+    let poll = Duration::from_millis(10);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let cancelled = cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed));
+        if cancelled || remaining.is_some_and(|remaining| remaining.is_zero()) {
+            // There is no such group unless `child` leads it, since its ID
+            // cannot be reused while `child` is still around to be waited on
+            #[cfg(unix)]
+            {
+                // SAFETY: just sends a signal
+                unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+            }
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(remaining.map_or(poll, |remaining| remaining.min(poll)));
+    }
+}
+
+/// Sleep for `delay`, or only until `deadline` if that comes first
+pub(crate) fn sleep_until(delay: Duration, deadline: Option<Instant>) {
+    let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+    thread::sleep(remaining.map_or(delay, |remaining| remaining.min(delay)));
+}
+
+/// The first character outside of quotes in `command` that only a shell would
+/// understand, like the `|` in a pipeline
+fn shell_syntax(command: &str) -> Option<char> {
+    let mut quotes = braces::Quotes::default();
+    command.chars().find(|&c| {
+        let (quoted, expands) = (quotes.any(), quotes.expands());
+        quotes.update(c);
+        if quoted {
+            expands && "$`".contains(c)
+        } else {
+            "|&;<>()$`*?\n".contains(c)
+        }
+    })
+}
+
+/// Replace each `${NAME}` in `value` with `lookup(NAME)`, or nothing if that
+/// is not set
+fn expand_vars(value: &str, lookup: impl Fn(&str) -> Option<OsString>) -> OsString {
+    let mut expanded = OsString::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start + 2..].find('}') else {
+            break;
+        };
+        expanded.push(&rest[..start]);
+        if let Some(value) = lookup(&rest[start + 2..start + 2 + len]) {
+            expanded.push(value);
+        }
+        rest = &rest[start + 3 + len..];
+    }
+    expanded.push(rest);
+    expanded
+}
+
+/// Read arguments for [`Plan::set_args`] from a file, one per line. Blank
+/// lines and `#` comments are ignored.
+pub fn read_args_file(path: &Path) -> anyhow::Result<Vec<String>> {
+    let raw = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    Ok(parse_args_file(&raw))
+}
+
+fn parse_args_file(raw: &str) -> Vec<String> {
+    raw.lines()
+        .map(|line| {
+            // Only a `#` starting a word begins a comment, so `--color=#fff` is kept
+            let comment = line
+                .match_indices('#')
+                .find(|(i, _)| line[..*i].chars().last().is_none_or(char::is_whitespace));
+            let line = comment.map_or(line, |(i, _)| &line[..i]);
+            line.trim().to_owned()
+        })
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Replace a leading `~` in `path` with the current user's home directory.
+/// Other users' home directories (`~user`) are left alone.
+pub fn expand_home(path: &Path) -> PathBuf {
+    expand_home_in(path, home_dir().as_deref())
+}
+
+fn expand_home_in(path: &Path, home: Option<&Path>) -> PathBuf {
+    let (Some(home), Ok(rest)) = (home, path.strip_prefix("~")) else {
+        return path.to_path_buf();
+    };
+    if rest.as_os_str().is_empty() {
+        home.to_path_buf()
+    } else {
+        home.join(rest)
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    let key = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    env::var_os(key)
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+fn detect_ci() -> bool {
+    let set = |key| env::var_os(key).is_some_and(|value| !value.is_empty() && value != "false");
+    set("CI") || set("GITHUB_ACTIONS")
+}
+
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt as _;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+
+    status.code().unwrap_or(1)
+}
+
+/// Whether `program` is Windows' `cmd`, which parses its command line itself
+fn is_cmd(program: &str) -> bool {
+    Path::new(program)
+        .file_stem()
+        .is_some_and(|stem| stem.eq_ignore_ascii_case("cmd"))
+}
+
+fn check_min_version(required: &str, current: &str) -> anyhow::Result<()> {
+    /// One dot-separated part of a pre-release. Numeric ones sort first.
+    #[derive(PartialEq, Eq, PartialOrd, Ord)]
+    enum Identifier<'a> {
+        Numeric(u64),
+        Alphanumeric(&'a str),
+    }
+
+    /// Numeric `major.minor.patch`, with minor and patch optional, then any
+    /// pre-release, in a form that sorts by semver precedence. Build metadata
+    /// after a `+` is ignored.
+    fn parse(version: &str) -> Option<([u64; 3], bool, Vec<Identifier<'_>>)> {
+        let version = version
+            .split_once('+')
+            .map_or(version, |(version, _)| version);
+        let (release, pre) = match version.split_once('-') {
+            Some((release, pre)) => (release, Some(pre)),
+            None => (version, None),
+        };
+
+        let mut parts = [0; 3];
+        let mut split = release.split('.');
+        for part in &mut parts {
+            if let Some(raw) = split.next() {
+                *part = raw.parse().ok()?;
+            }
+        }
+        if split.next().is_some() {
+            return None;
+        }
+
+        let identifiers = pre
+            .into_iter()
+            .flat_map(|pre| pre.split('.'))
+            .map(|raw| {
+                let valid = raw.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+                if raw.is_empty() || !valid {
+                    None
+                } else if raw.bytes().all(|b| b.is_ascii_digit()) {
+                    raw.parse().ok().map(Identifier::Numeric)
+                } else {
+                    Some(Identifier::Alphanumeric(raw))
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+        // Pre-releases come before the release they are for
+        Some((parts, pre.is_none(), identifiers))
+    }
+
+    let Some(parsed) = parse(required) else {
+        bail!("invalid min-version `{required}`, expected eg `1.2.3` or `1.2.3-rc.1`");
+    };
+    let Some(current_parsed) = parse(current) else {
+        bail!("cannot compare min-version `{required}` with this wrun's version, {current}");
+    };
+    if current_parsed < parsed {
+        bail!(
+            "this project requires wrun {required} or newer, but this is {current}. Please \
+             upgrade wrun."
+        );
+    }
+    Ok(())
+}
+
+fn read_package(dir: &Path, separator: Separator) -> anyhow::Result<Package> {
+    let mut package = package_from_path(&dir.join(PACKAGE_FILE), separator)?;
+    include_task_files(dir, &mut package, separator)?;
+    Ok(package)
+}
+
+/// The `*.toml` files in `dir`'s task file directory, in lexical order
+fn task_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let dir = dir.join(TASK_FILES_DIR);
+    if !fs::exists(&dir)? {
+        return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Add the tasks from each of `dir`'s task files to `package`. Task names must
+/// be unique across all of a package's files.
+fn include_task_files(
+    dir: &Path,
+    package: &mut Package,
+    separator: Separator,
+) -> anyhow::Result<()> {
+    for path in task_files(dir)? {
+        let extra = package_from_path(&path, separator)
+            .with_context(|| format!("loading {}", path.display()))?;
+        for (name, task) in extra.tasks.0 {
+            if package.tasks.0.get_index(&name).is_some() {
+                bail!("task `{name}` from {} is already defined", path.display());
+            }
+            package.tasks.0.insert(name, task);
+        }
+    }
+    Ok(())
+}
+
+/// Merge in the tasks of every package `package` (found at `path`) extends
+fn inherit(
+    root: &Path,
+    path: &Path,
+    mut package: Package,
+    separator: Separator,
+) -> anyhow::Result<Package> {
+    let mut chain = vec![path.to_path_buf()];
+    let mut current = path.to_path_buf();
+
+    while let Some(extends) = package.extends.take() {
+        let base_path = base_package_path(&current, &extends, "extends")?;
+        if chain.contains(&base_path) {
+            chain.push(base_path);
+            let chain = chain
+                .iter()
+                .map(|path| format!("/{}", path.display()))
+                .collect::<Vec<_>>();
+            bail!("package inheritance cycle: {}", chain.join(" -> "));
+        }
+
+        let base = read_package(&root.join(&base_path), separator)
+            .with_context(|| format!("loading base package from {}", base_path.display()))?;
+        package.tasks.inherit(base.tasks);
+        package.extends = base.extends;
+
+        chain.push(base_path.clone());
+        current = base_path;
+    }
+
+    Ok(package)
+}
+
+/// Resolve `other` (from the package option `key`) relative to the package at
+/// `path`
+fn base_package_path(path: &Path, other: &Path, key: &str) -> anyhow::Result<PathBuf> {
+    let mut base_path = PathBuf::new();
+    for component in path.join(other).components() {
+        match component {
+            path::Component::Normal(part) => base_path.push(part),
+            path::Component::CurDir => {}
+            path::Component::ParentDir if base_path.pop() => {}
+            _ => bail!(
+                "`{key} = {:?}` in /{} escapes the project root",
+                other.display(),
+                path.display(),
+            ),
+        }
+    }
+    Ok(base_path)
+}
+
+fn package_from_path(path: &Path, separator: Separator) -> anyhow::Result<Package> {
+    let raw = fs::read_to_string(path)?;
+    let mut package: Package = data::from_toml(&raw, Some(separator))?;
+    package.set_sources(path, &raw);
+    Ok(package)
+}
+
+#[cfg(test)]
+impl Context {
+    /// Build a context from in-memory package definitions. The root package
+    /// must be first and named `""`.
+    pub(crate) fn for_test(packages: &[(&str, &str)]) -> Self {
+        let mut packages = packages.iter();
+        let (name, root_toml) = packages.next().unwrap();
+        assert_eq!(*name, "", "root package must come first");
+
+        let root = PathBuf::from("/nonexistent");
+        let (mut context, _) = Self::new(root, data::from_toml(root_toml, None).unwrap());
+        for (name, toml) in packages {
+            let package = data::from_toml(toml, Some(context.separator)).unwrap();
+            context.packages.insert((*name).to_owned(), package);
+        }
+        context
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::ffi::OsStr;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// A project written to a temporary directory, deleted on drop
+    pub(crate) struct TestProject(pub(crate) PathBuf);
+
+    impl TestProject {
+        pub(crate) fn new(files: &[(&str, &str)]) -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+            let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let root = std::env::temp_dir().join(format!("wrun-test-{}-{id}", process::id()));
+            for (path, contents) in files {
+                let path = root.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, contents).unwrap();
+            }
+
+            Self(root)
+        }
+
+        pub(crate) fn context(&self) -> Context {
+            Context::from_directory(&self.0).unwrap()
+        }
+    }
+
+    impl Drop for TestProject {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn env_var<'a>(command: &'a Command, key: &str) -> Option<&'a OsStr> {
+        command
+            .get_envs()
+            .find_map(|(k, v)| (k == key).then_some(v).flatten())
+    }
+
+    #[test]
+    fn env_files() {
+        let project = TestProject::new(&[
+            (
+                PROJECT_FILE,
+                r#"
+                env-files = [".env"]
+                tasks.test.run = "true"
+                "#,
+            ),
+            (".env", "FROM_ENV_FILE=yes"),
+        ]);
+        let task = TaskName::new("/test").relative_to("").unwrap();
+
+        let mut context = project.context();
+        let mut plan = context.plan();
+        plan.push(&task).unwrap();
+        let command = plan.command(&plan.plan[0]).unwrap();
+        assert_eq!(env_var(&command, "FROM_ENV_FILE"), Some(OsStr::new("yes")));
+
+        let mut context = project.context();
+        context.disable_env_files();
+        let mut plan = context.plan();
+        plan.push(&task).unwrap();
+        let command = plan.command(&plan.plan[0]).unwrap();
+        assert_eq!(env_var(&command, "FROM_ENV_FILE"), None);
+        assert!(env_var(&command, "WRUN").is_some());
+        assert!(env_var(&command, "ROOT").is_some());
+    }
+
+    #[test]
+    fn conditional_env_files() {
+        let project = TestProject::new(&[
+            (
+                PROJECT_FILE,
+                r#"
+                env-files = [
+                    ".env",
+                    { path = ".env.ci", when = "ci" },
+                    { path = ".env.local", when = "interactive" },
+                ]
+                tasks.test.run = "true"
+                "#,
+            ),
+            (".env", "ALWAYS=yes"),
+            (".env.ci", "FROM_CI=yes"),
+            (".env.local", "FROM_LOCAL=yes"),
+        ]);
+        let task = TaskName::new("/test").relative_to("").unwrap();
+
+        for ci in [true, false] {
+            let mut context = project.context();
+            context.ci = ci;
+            let mut plan = context.plan();
+            plan.push(&task).unwrap();
+            let command = plan.command(&plan.plan[0]).unwrap();
+
+            let expected = |set: bool| set.then_some(OsStr::new("yes"));
+            assert_eq!(env_var(&command, "ALWAYS"), expected(true));
+            assert_eq!(env_var(&command, "FROM_CI"), expected(ci));
+            assert_eq!(env_var(&command, "FROM_LOCAL"), expected(!ci));
+        }
+    }
+
+    #[test]
+    fn env_file_in_home() {
+        let home = TestProject::new(&[(".config/wrun.env", "FROM_HOME=yes")]);
+        let project = TestProject::new(&[(
+            PROJECT_FILE,
+            r#"
+            env-files = ["~/.config/wrun.env"]
+            tasks.test.run = "true"
+            "#,
+        )]);
+
+        let mut context = project.context();
+        context.home = Some(home.0.clone());
+        let mut plan = context.plan();
+        plan.push(&TaskName::new("/test").relative_to("").unwrap())
+            .unwrap();
+        let command = plan.command(&plan.plan[0]).unwrap();
+        assert_eq!(env_var(&command, "FROM_HOME"), Some(OsStr::new("yes")));
+    }
+
+    #[test]
+    fn home_expansion() {
+        let home = Some(Path::new("/home/me"));
+        let expand = |path| expand_home_in(Path::new(path), home);
+
+        assert_eq!(expand("~"), Path::new("/home/me"));
+        assert_eq!(expand("~/.env"), Path::new("/home/me/.env"));
+        assert_eq!(expand("~other/.env"), Path::new("~other/.env"));
+        assert_eq!(expand("dir/~/.env"), Path::new("dir/~/.env"));
+        assert_eq!(
+            expand_home_in(Path::new("~/.env"), None),
+            Path::new("~/.env")
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let project = TestProject::new(&[(
+            PROJECT_FILE,
+            r#"
+            tasks.ok.run = "true"
+            tasks.fail.run = ["exit 3", "true"]
+            tasks.later.run = "true"
+            "#,
+        )]);
+
+        let mut context = project.context();
+        let mut plan = context.plan();
+        for task in ["/ok", "/fail", "/later"] {
+            plan.push(&TaskName::new(task).relative_to("").unwrap())
+                .unwrap();
+        }
+        let summary = plan.execute(|_| {}).unwrap();
+
+        let tasks = summary
+            .tasks()
+            .map(|(name, status)| (name.display(Separator::Slash).to_string(), status.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(tasks, [
+            ("/ok".to_owned(), TaskStatus::Ran),
+            ("/fail".to_owned(), TaskStatus::Failed { code: 3 }),
+            (
+                "/later".to_owned(),
+                TaskStatus::Skipped(SkipReason::EarlierFailure)
+            ),
+        ]);
+        assert_eq!(summary.exit_code(), Some(3));
+        assert!(summary.is_interesting());
+    }
+
+    #[test]
+    fn plan_hash() {
+        let hash = |env: &str, command: &str| {
+            let project = TestProject::new(&[
+                (
+                    PROJECT_FILE,
+                    &format!(
+                        r#"
+                        env-files = [".env"]
+                        packages = ["pkg"]
+                        tasks.test.run = [{{ task = "pkg/build" }}, "{command}"]
+                        "#
+                    ),
+                ),
+                ("pkg/wrun.toml", r#"tasks.build.run = "make""#),
+                (".env", env),
+            ]);
+
+            let mut context = project.context();
+            let mut plan = context.plan();
+            plan.push(&TaskName::new("/test").relative_to("").unwrap())
+                .unwrap();
+            plan.hash().unwrap()
+        };
+
+        let original = hash("A=1\nB=2", "cargo test");
+        assert_eq!(original, hash("B=2\nA=1", "cargo test"));
+        assert_ne!(original, hash("A=1\nB=2", "cargo test --release"));
+        assert_ne!(original, hash("A=1\nB=3", "cargo test"));
+    }
+
+    #[test]
+    fn plan_hash_options() {
+        let hash = |project: &str, env: &[(&str, &str)]| {
+            let mut context = Context::for_test(&[("", project)]);
+            for (key, value) in env {
+                context.set_env(*key, *value);
+            }
+            let mut plan = context.plan();
+            plan.push(&TaskName::new("/test").relative_to("").unwrap())
+                .unwrap();
+            plan.hash().unwrap()
+        };
+
+        let base = r#"tasks.test.run = "grep x file""#;
+        let original = hash(base, &[]);
+        assert_eq!(original, hash(base, &[]));
+
+        let one = hash(base, &[("X", "1")]);
+        assert_ne!(original, one);
+        assert_ne!(one, hash(base, &[("X", "2")]));
+        assert_eq!(one, hash(base, &[("X", "2"), ("X", "1")]));
+
+        let changed = [
+            "clean-env = true",
+            "env-passthrough = ['HOME']",
+            "tasks.test.shell = ['bash', '-c']",
+            "tasks.test.retry.attempts = 3",
+            "tasks.test.pty = true",
+            "tasks.test.nice = 10",
+        ];
+        for option in changed {
+            assert_ne!(
+                original,
+                hash(&format!("{base}\n{option}"), &[]),
+                "{option}"
+            );
+        }
+        for run in [
+            "{ cmd = 'grep x file', ok-codes = [1] }",
+            "{ cmd = 'grep x file', capture = 'x' }",
+        ] {
+            assert_ne!(
+                original,
+                hash(&format!("tasks.test.run = {run}"), &[]),
+                "{run}"
+            );
+        }
+    }
+
+    #[test]
+    fn deprecated_task_warning() {
+        let project = TestProject::new(&[(
+            PROJECT_FILE,
+            r#"
+            tasks.old = { run = { task = "new" }, deprecated = "use new instead" }
+            tasks.new.run = "true"
+            tasks.wrapper.run = { task = "old" }
+            "#,
+        )]);
+        let warnings = |task: &str| {
+            let mut context = project.context();
+            let mut plan = context.plan();
+            plan.push(&TaskName::new(task).relative_to("").unwrap())
+                .unwrap();
+            plan.warnings().map(str::to_owned).collect::<Vec<_>>()
+        };
+
+        assert_eq!(warnings("/old"), ["/old is deprecated: use new instead"]);
+        assert!(warnings("/new").is_empty());
+        assert!(warnings("/wrapper").is_empty());
+    }
+
+    fn clean_env_keys(project: &str) -> Vec<String> {
+        let project = TestProject::new(&[(PROJECT_FILE, project), (".env", "FROM_ENV_FILE=yes")]);
+
+        let mut context = project.context();
+        let mut plan = context.plan();
+        plan.push(&TaskName::new("/test").relative_to("").unwrap())
+            .unwrap();
+        let output = plan.command(&plan.plan[0]).unwrap().output().unwrap();
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.split_once('=').map(|(key, _)| key.to_owned()))
+            .collect()
+    }
+
+    fn inherited_keys() -> impl Iterator<Item = String> {
+        std::env::vars()
+            .map(|(key, _)| key)
+            .filter(|key| !CLEAN_ENV_DEFAULTS.contains(&key.as_str()))
+    }
+
+    #[test]
+    fn clean_env() {
+        let inherited = inherited_keys()
+            .next()
+            .expect("tests run with at least one inherited variable");
+
+        let keys = clean_env_keys(
+            r#"
+            env-files = [".env"]
+            clean-env = true
+            tasks.test.run = "env"
+            "#,
+        );
+
+        assert!(keys.iter().any(|key| key == "FROM_ENV_FILE"));
+        assert!(keys.iter().any(|key| key == "WRUN"));
+        assert!(keys.iter().any(|key| key == "PATH"));
+        assert!(!keys.contains(&inherited), "{inherited} was inherited");
+    }
+
+    #[test]
+    fn env_passthrough() {
+        let mut inherited = inherited_keys();
+        let exact = inherited.next().unwrap();
+        let prefix = inherited.next().unwrap()[..1].to_owned();
+
+        let keys = clean_env_keys(&format!(
+            r#"
+            env-files = [".env"]
+            clean-env = true
+            env-passthrough = ["{exact}", "{prefix}*"]
+            tasks.test.run = "env"
+            "#
+        ));
+
+        assert!(keys.contains(&exact));
+        assert!(keys.iter().any(|key| key.starts_with(&prefix)));
+        // Set by the shell itself
+        let shell = ["PWD", "OLDPWD", "SHLVL", "_"];
+        for key in &keys {
+            let allowed = CLEAN_ENV_DEFAULTS.contains(&key.as_str())
+                || ["FROM_ENV_FILE", "WRUN", "ROOT"].contains(&key.as_str())
+                || shell.contains(&key.as_str())
+                || *key == exact
+                || key.starts_with(&prefix);
+            assert!(allowed, "{key} should not have been passed through");
+        }
+    }
+
+    #[test]
+    fn has_task() {
+        let project = TestProject::new(&[
+            (
+                PROJECT_FILE,
+                r#"
+                packages = ["listed"]
+                tasks.root.run = "true"
+                "#,
+            ),
+            ("listed/wrun.toml", r#"tasks.listed.run = "true""#),
+            ("unlisted/wrun.toml", r#"tasks.unlisted.run = "true""#),
+        ]);
+        let mut context = project.context();
+        let mut has_task = |name| context.has_task(&TaskName::new(name).relative_to("").unwrap());
+
+        assert!(has_task("/root"));
+        assert!(has_task("listed/listed"));
+        assert!(has_task("unlisted/unlisted"));
+
+        assert!(!has_task("/missing"));
+        assert!(!has_task("listed/missing"));
+        assert!(!has_task("unlisted/missing"));
+        assert!(!has_task("nonexistent/task"));
+    }
+
+    #[test]
+    fn empty_package_warning() {
+        let project = TestProject::new(&[
+            (PROJECT_FILE, r#"packages = ["empty", "full"]"#),
+            ("empty/wrun.toml", ""),
+            ("full/wrun.toml", r#"tasks.test.run = "true""#),
+        ]);
+
+        let context = project.context();
+        let warnings = context.warnings().collect::<Vec<_>>();
+        assert_eq!(warnings, ["package empty does not define any tasks"]);
+    }
+
+    #[test]
+    fn prelude() {
+        let project = TestProject::new(&[
+            (
+                PROJECT_FILE,
+                r#"
+                prelude = "check"
+                packages = ["pkg"]
+                tasks.check.run = "echo check"
+                "#,
+            ),
+            ("pkg/wrun.toml", r#"tasks.test.run = "echo test""#),
+        ]);
+        let commands = |requested: &[&str]| {
+            let requested = requested
+                .iter()
+                .map(|name| TaskName::new(name).relative_to("pkg").unwrap())
+                .collect::<Vec<_>>();
+
+            let mut context = project.context();
+            let mut plan = context.plan();
+            plan.push_prelude(&requested).unwrap();
+            for task in &requested {
+                plan.push(task).unwrap();
+            }
+            plan.plan
+                .iter()
+                .map(|entry| entry.command.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(commands(&["test"]), ["echo check", "echo test"]);
+        assert_eq!(commands(&["test", "/check"]), ["echo test", "echo check"]);
+    }
+
+    #[test]
+    fn silent_in_ci() {
+        let project = TestProject::new(&[(
+            PROJECT_FILE,
+            r#"
+            tasks.test.silent = "ci"
+            tasks.test.run = ["one", { cmd = "two", silent = "interactive" }]
+            "#,
+        )]);
+        let silent = |ci| {
+            let mut context = project.context();
+            context.ci = ci;
+            let mut plan = context.plan();
+            plan.push(&TaskName::new("/test").relative_to("").unwrap())
+                .unwrap();
+            plan.plan.iter().map(PlanEntry::silent).collect::<Vec<_>>()
+        };
+
+        assert_eq!(silent(true), [true, false]);
+        assert_eq!(silent(false), [false, true]);
+    }
+
+    #[test]
+    fn entry_env() {
+        let project = TestProject::new(&[
+            (
+                PROJECT_FILE,
+                r#"
+                env-files = [".env"]
+                tasks.test.run = "true"
+                "#,
+            ),
+            (".env", "FROM_ENV_FILE=yes"),
+        ]);
+
+        let mut context = project.context();
+        let mut plan = context.plan();
+        plan.push(&TaskName::new("/test").relative_to("").unwrap())
+            .unwrap();
+        let env = plan.env().unwrap();
+        let keys = env.keys().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(keys, ["FROM_ENV_FILE", "ROOT", "WRUN"]);
+        assert_eq!(env["FROM_ENV_FILE"], "yes");
+    }
+
+    #[test]
+    fn extends() {
+        let project = TestProject::new(&[
+            (PROJECT_FILE, r#"packages = ["app"]"#),
+            (
+                "common/wrun.toml",
+                r#"
+                tasks.lint.run = "echo common lint"
+                tasks.test.run = "echo common test"
+                "#,
+            ),
+            (
+                "app/wrun.toml",
+                r#"
+                extends = "../common"
+                tasks.test.run = "echo app test"
+                tasks.build.run = "echo app build"
+                "#,
+            ),
+        ]);
+
+        let context = project.context();
+        let (_, package) = context.packages().find(|(name, _)| *name == "app").unwrap();
+        let tasks = package
+            .tasks()
+            .iter()
+            .map(|(name, task)| {
+                let Run::Command { command, .. } = &task.run()[0] else {
+                    unreachable!()
+                };
+                (name, command.as_str())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(tasks, [
+            ("build", "echo app build"),
+            ("test", "echo app test"),
+            ("lint", "echo common lint"),
+        ]);
+    }
+
+    #[test]
+    fn extends_cycle() {
+        let project = TestProject::new(&[
+            (PROJECT_FILE, r#"packages = ["a"]"#),
+            ("a/wrun.toml", r#"extends = "../b""#),
+            ("b/wrun.toml", r#"extends = "../a""#),
+        ]);
+
+        let error = Context::from_directory(&project.0).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "loading package from a: package inheritance cycle: /a -> /b -> /a"
+        );
+    }
+
+    #[test]
+    fn profiles() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            tasks.build.run = "cargo build"
+            tasks.build.profiles.release = ["cargo build --release", { task = "strip" }]
+            tasks.strip.run = "strip target/release/wrun"
+            "#,
+        )]);
+        let mut commands = |profile: Option<&str>| {
+            context.profile = profile.map(str::to_owned);
+            let mut plan = context.plan();
+            plan.push(&TaskName::new("/build").relative_to("").unwrap())
+                .unwrap();
+            plan.plan
+                .into_iter()
+                .map(|entry| entry.command)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(commands(None), ["cargo build"]);
+        assert_eq!(commands(Some("release")), [
+            "cargo build --release",
+            "strip target/release/wrun"
+        ]);
+        assert_eq!(commands(Some("debug")), ["cargo build"]);
+    }
+
+    #[test]
+    fn task_source() {
+        let project = TestProject::new(&[
+            (
+                PROJECT_FILE,
+                "packages = [\"app\"]\ntasks.root.run = \"true\"",
+            ),
+            ("common/wrun.toml", "\n\n[tasks.lint]\nrun = \"true\""),
+            (
+                "app/wrun.toml",
+                "extends = \"../common\"\ntasks.build.run = \"true\"",
+            ),
+        ]);
+
+        let context = project.context();
+        let source = |name: &str| {
+            let name = TaskName::new(name).relative_to("app").unwrap();
+            let (path, line) = context.task_source(&name).unwrap();
+            (path.strip_prefix(&project.0).unwrap().to_owned(), line)
+        };
+
+        assert_eq!(source("build"), (PathBuf::from("app/wrun.toml"), 2));
+        assert_eq!(source("lint"), (PathBuf::from("common/wrun.toml"), 3));
+        assert_eq!(source("/root"), (PathBuf::from(PROJECT_FILE), 2));
+    }
+
+    #[test]
+    fn path_vars_are_merged() {
+        let project = TestProject::new(&[
+            (
+                PROJECT_FILE,
+                r#"
+                env-files = [".env"]
+                path-vars = ["PATH", "WRUN_TEST_UNSET_PATH"]
+                tasks.test.run = "true"
+                "#,
+            ),
+            (
+                ".env",
+                "PATH=/from/env-file\nWRUN_TEST_UNSET_PATH=/only/env-file",
+            ),
+        ]);
+
+        let mut context = project.context();
+        let plan = context.plan();
+        let env = plan.env().unwrap();
+
+        let inherited = env::var_os("PATH").unwrap();
+        let expected = iter::once(PathBuf::from("/from/env-file"))
+            .chain(env::split_paths(&inherited))
+            .collect::<Vec<_>>();
+        assert_eq!(env::split_paths(&env["PATH"]).collect::<Vec<_>>(), expected);
+        assert_eq!(env["WRUN_TEST_UNSET_PATH"], "/only/env-file");
+    }
+
+    #[test]
+    fn empty_plan() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            tasks.empty.run = []
+            tasks.wrapper.run = { task = "empty" }
+            tasks.full.run = "true"
+            "#,
+        )]);
+        let mut is_empty = |tasks: &[&str]| {
+            let mut plan = context.plan();
+            for task in tasks {
+                plan.push(&TaskName::new(task).relative_to("").unwrap())
+                    .unwrap();
+            }
+            plan.is_empty()
+        };
+
+        assert!(is_empty(&[]));
+        assert!(is_empty(&["empty", "wrapper"]));
+        assert!(!is_empty(&["empty", "full"]));
+    }
+
+    #[test]
+    fn templated_description() {
+        let mut context = Context::for_test(&[
+            ("", r#"tasks.build.desc = "Build {{package}} {{profile}}""#),
+            ("pkg", r#"tasks.build.desc = "Build {{package}} {{nope}}""#),
+        ]);
+        context.set_profile("release");
+        let description = |package: &str| {
+            let name = TaskName::new("build").relative_to(package).unwrap();
+            let package = context.packages.get(package).unwrap();
+            let task = package.tasks.0.get("build").unwrap();
+            context.description(&name, task).unwrap()
+        };
+
+        assert_eq!(description(""), ("Build / release".to_owned(), vec![]));
+        assert_eq!(
+            description("pkg"),
+            ("Build pkg {{nope}}".to_owned(), vec![
+                "unknown placeholder `{{nope}}` in the description of pkg/build".to_owned()
+            ])
+        );
+    }
+
+    #[test]
+    fn shadowed_root_task_warning() {
+        let project = TestProject::new(&[
+            (
+                PROJECT_FILE,
+                r#"
+                packages = ["pkg"]
+                tasks.build.run = "true"
+                tasks.lint.run = "true"
+                "#,
+            ),
+            (
+                "pkg/wrun.toml",
+                r#"
+                tasks.build.run = "true"
+                tasks.test.run = "true"
+                "#,
+            ),
+        ]);
+
+        let context = project.context();
+        let warnings = context.warnings().collect::<Vec<_>>();
+        assert_eq!(warnings, ["pkg/build shadows the root task /build"]);
+    }
+
+    #[test]
+    fn task_files_dir() {
+        let project = TestProject::new(&[
+            (
+                PROJECT_FILE,
+                r#"
+                packages = ["pkg"]
+                tasks.root.run = "true"
+                "#,
+            ),
+            ("wrun.d/extra.toml", r#"tasks.extra-root.run = "true""#),
+            ("pkg/wrun.toml", r#"tasks.main.run = "true""#),
+            ("pkg/wrun.d/b.toml", r#"tasks.b.run = "true""#),
+            ("pkg/wrun.d/a.toml", r#"tasks.a.run = "true""#),
+            ("pkg/wrun.d/ignored.txt", r#"tasks.ignored.run = "true""#),
+        ]);
+
+        let context = project.context();
+        let tasks = |package: &str| {
+            let (_, package) = context
+                .packages()
+                .find(|(name, _)| *name == package)
+                .unwrap();
+            package
+                .tasks()
+                .iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(tasks(""), ["root", "extra-root"]);
+        assert_eq!(tasks("pkg"), ["main", "a", "b"]);
+
+        let name = TaskName::new("b").relative_to("pkg").unwrap();
+        let (path, line) = context.task_source(&name).unwrap();
+        assert_eq!(path, project.0.join("pkg/wrun.d/b.toml"));
+        assert_eq!(line, 1);
+    }
+
+    #[test]
+    fn duplicate_task_across_files() {
+        let project = TestProject::new(&[
+            (PROJECT_FILE, r#"packages = ["pkg"]"#),
+            ("pkg/wrun.toml", r#"tasks.build.run = "true""#),
+            ("pkg/wrun.d/build.toml", r#"tasks.build.run = "false""#),
+        ]);
+
+        let error = Context::from_directory(&project.0).unwrap_err();
+        let path = project.0.join("pkg/wrun.d/build.toml");
+        assert_eq!(
+            format!("{error:#}"),
+            format!(
+                "loading package from pkg: task `build` from {} is already defined",
+                path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn min_version() {
+        let load = |version: &str| {
+            let project =
+                TestProject::new(&[(PROJECT_FILE, &format!("min-version = {version:?}"))]);
+            Context::from_directory(&project.0).map(|_| ())
+        };
+
+        assert!(load("0.0.1").is_ok());
+        assert!(load("0").is_ok());
+        assert!(load(env!("CARGO_PKG_VERSION")).is_ok());
+
+        let error = load("999.0").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "this project requires wrun 999.0 or newer, but this is {}. Please upgrade wrun.",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        assert!(load("1.x").is_err());
+        assert!(load("1.2.3.4").is_err());
+
+        assert!(check_min_version("0.2", "0.2.0+build.5").is_ok());
+        assert!(check_min_version("0.2.1", "0.2.0+build.5").is_err());
+        assert!(check_min_version("0.2", "nightly").is_err());
+
+        // Pre-releases come before the release, ordered by their identifiers
+        assert!(check_min_version("0.2", "0.2.0-dev").is_err());
+        assert!(check_min_version("0.2.0-dev", "0.2.0-dev").is_ok());
+        assert!(check_min_version("0.2.0-dev", "0.2.0").is_ok());
+        assert!(check_min_version("0.1.9", "0.2.0-dev").is_ok());
+        for [older, newer] in [
+            ["1.0.0-alpha", "1.0.0-alpha.1"],
+            ["1.0.0-alpha.1", "1.0.0-alpha.beta"],
+            ["1.0.0-alpha.beta", "1.0.0-beta"],
+            ["1.0.0-beta.2", "1.0.0-beta.11"],
+            ["1.0.0-beta.11", "1.0.0-rc.1"],
+        ] {
+            assert!(check_min_version(older, newer).is_ok(), "{older} < {newer}");
+            assert!(
+                check_min_version(newer, older).is_err(),
+                "{older} < {newer}"
+            );
+        }
+        assert!(check_min_version("1.0.0-rc.1+build.5", "1.0.0-rc.1").is_ok());
+        assert!(load("1.0.0-").is_err());
+        assert!(load("1.0.0-a..b").is_err());
+    }
+
+    #[test]
+    fn min_version_checked_first() {
+        let project = TestProject::new(&[(
+            PROJECT_FILE,
+            "min-version = \"999.0\"\nclean-env = { new-syntax = true }",
+        )]);
+        let error = Context::from_directory(&project.0).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("this project requires wrun 999.0")
+        );
+    }
+
+    #[test]
+    fn for_each() {
+        let project = TestProject::new(&[
+            (
+                PROJECT_FILE,
+                r#"
+                tasks.install.for-each = "packages/*"
+                tasks.install.run = ["npm install {{dir}}", { task = "done" }]
+                tasks.done = { run = "echo done", repeatable = true }
+                tasks.empty.for-each = "missing/*"
+                tasks.empty.run = "true"
+                "#,
+            ),
+            ("packages/b/package.json", "{}"),
+            ("packages/a/package.json", "{}"),
+            ("packages/not-a-dir", ""),
+        ]);
+
+        let mut context = project.context();
+        let mut plan = context.plan();
+        plan.push(&TaskName::new("/install").relative_to("").unwrap())
+            .unwrap();
+        let entries = plan
+            .entries()
+            .iter()
+            .map(|entry| {
+                let directory = entry.directory().strip_prefix(&project.0).unwrap();
+                (directory.to_owned(), entry.command())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(entries, [
+            (PathBuf::from("packages/a"), "npm install packages/a"),
+            (PathBuf::new(), "echo done"),
+            (PathBuf::from("packages/b"), "npm install packages/b"),
+            (PathBuf::new(), "echo done"),
+        ]);
+
+        plan.push(&TaskName::new("/empty").relative_to("").unwrap())
+            .unwrap();
+        assert_eq!(plan.entries().len(), 4);
+        let warnings = plan.warnings().collect::<Vec<_>>();
+        assert_eq!(warnings, [
+            "for-each pattern `missing/*` of /empty does not match any directories"
+        ]);
+    }
+
+    #[test]
+    fn failed_stderr_tail() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"tasks.fail.run = "echo a >&2; echo b >&2; echo c >&2; exit 3""#,
+        )]);
+        context.root = env::temp_dir();
+        context.limit_output(100);
+        context.keep_stderr_tail(2);
+        context.discard_output = true;
+
+        let task = TaskName::new("/fail").relative_to("").unwrap();
+        let mut plan = context.plan();
+        plan.push(&task).unwrap();
+        let summary = plan.execute(|_| {}).unwrap();
+
+        assert_eq!(summary.exit_code(), Some(3));
+        let (failed, tail) = summary.stderr_tail().unwrap();
+        assert_eq!(*failed, task);
+        assert_eq!(tail, ["b", "c"]);
+    }
+
+    #[test]
+    fn max_time() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            tasks.slow.run = "sleep 5"
+            tasks.after.run = "true"
+            "#,
+        )]);
+        context.root = env::temp_dir();
+        context.limit_time(Duration::from_millis(200));
+
+        let slow = TaskName::new("/slow").relative_to("").unwrap();
+        let after = TaskName::new("/after").relative_to("").unwrap();
+        let mut plan = context.plan();
+        plan.push(&slow).unwrap();
+        plan.push(&after).unwrap();
+
+        let start = Instant::now();
+        let summary = plan.execute(|_| {}).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let statuses = summary.tasks().collect::<Vec<_>>();
+        assert_eq!(statuses, [
+            (&slow, &TaskStatus::TimedOut),
+            (&after, &TaskStatus::Skipped(SkipReason::TimeLimit)),
+        ]);
+        assert_eq!(summary.exit_code(), Some(124));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn max_time_stops_what_commands_start() {
+        let marker = env::temp_dir().join(format!("wrun-max-time-{}", std::process::id()));
+        let touch = format!("(sleep 0.5; touch '{}'); true", marker.display());
+        // Each leaves something behind holding on to the output if only the
+        // shell is killed
+        for (run, quiet) in [
+            (format!("{touch:?}"), false),
+            (format!("{touch:?}"), true),
+            ("'sleep 30 | cat'".to_owned(), false),
+            ("'sleep 30 | cat'".to_owned(), true),
+            (
+                "{ cmd = 'sleep 30 | cat', capture = 'out' }".to_owned(),
+                false,
+            ),
+            (
+                "'exit 1'\ntasks.slow.retry = { attempts = 2, base-delay = 30 }".to_owned(),
+                false,
+            ),
+        ] {
+            let mut context = Context::for_test(&[("", &format!("tasks.slow.run = {run}"))]);
+            context.root = env::temp_dir();
+            context.limit_time(Duration::from_millis(200));
+            context.limit_output(10);
+            if quiet {
+                context.quiet();
+            }
+
+            let slow = TaskName::new("/slow").relative_to("").unwrap();
+            let mut plan = context.plan();
+            plan.push(&slow).unwrap();
+
+            let start = Instant::now();
+            let summary = plan.execute(|_| {}).unwrap();
+            assert!(start.elapsed() < Duration::from_secs(2), "{run}");
+            assert_eq!(summary.exit_code(), Some(124), "{run}");
+        }
+
+        thread::sleep(Duration::from_millis(600));
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn missing_current_exe() {
+        let mut context = Context::for_test(&[("", r#"tasks.test.run = "true""#)]);
+        let on_path = env::var_os("PATH").and_then(|path| search_path(&path, Path::new("wrun")));
+
+        let unavailable = Err(io::Error::other("unavailable"));
+        let mut plan = Plan::with_current_exe(&mut context, unavailable);
+        plan.push(&TaskName::new("/test").relative_to("").unwrap())
+            .unwrap();
+        let command = plan.command(&plan.plan[0]).unwrap();
+
+        assert_eq!(
+            env_var(&command, "WRUN"),
+            on_path.as_deref().map(Path::as_os_str)
+        );
+        assert_eq!(plan.warnings().next().is_none(), on_path.is_some());
+        assert!(env_var(&command, "ROOT").is_some());
+    }
+
+    #[test]
+    fn matrix() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            [tasks.test]
+            run = "echo {{matrix.name}}"
+            matrix.postgres = { DATABASE_URL = "postgres://localhost" }
+            matrix.sqlite = { DATABASE_URL = "sqlite::memory:" }
+            "#,
+        )]);
+
+        let mut plan = context.plan();
+        plan.push(&TaskName::new("/test").relative_to("").unwrap())
+            .unwrap();
+
+        let runs = plan
+            .entries()
+            .iter()
+            .map(|entry| {
+                let command = plan.command(entry).unwrap();
+                let url = env_var(&command, "DATABASE_URL").unwrap().to_owned();
+                (entry.command().to_owned(), url)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(runs, [
+            ("echo postgres".to_owned(), "postgres://localhost".into()),
+            ("echo sqlite".to_owned(), "sqlite::memory:".into()),
+        ]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn nice() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            tasks.test.run = 'test "$(ps -o ni= -p $$)" -eq 19'
+            tasks.test.nice = 19
+            "#,
+        )]);
+        context.root = env::temp_dir();
+
+        let mut plan = context.plan();
+        plan.push(&TaskName::new("/test").relative_to("").unwrap())
+            .unwrap();
+        let summary = plan.execute(|_| {}).unwrap();
+        assert_eq!(summary.exit_code(), None);
+    }
+
+    #[test]
+    fn brace_expansion() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            tasks.clean.run = "rm -rf {dist,build}/{{matrix.name}}"
+            tasks.clean.brace-expansion = true
+            tasks.clean.matrix.x = {}
+            tasks.shell.run = "rm -rf {dist,build}"
+            "#,
+        )]);
+
+        let mut plan = context.plan();
+        for task in ["/clean", "/shell"] {
+            plan.push(&TaskName::new(task).relative_to("").unwrap())
+                .unwrap();
+        }
+        let commands = plan
+            .entries()
+            .iter()
+            .map(PlanEntry::command)
+            .collect::<Vec<_>>();
+        assert_eq!(commands, ["rm -rf dist/x build/x", "rm -rf {dist,build}"]);
+    }
+
+    #[test]
+    fn stop_after() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            tasks.first.run = ["true", "true"]
+            tasks.second.run = ["true", "true"]
+            tasks.third.run = "true"
+            "#,
+        )]);
+        context.root = env::temp_dir();
+        context.stop_after(3);
+
+        let names = ["/first", "/second", "/third"]
+            .map(|name| TaskName::new(name).relative_to("").unwrap());
+        let mut plan = context.plan();
+        for name in &names {
+            plan.push(name).unwrap();
+        }
+        let ran = Cell::new(0);
+        let summary = plan.execute(|_| ran.set(ran.get() + 1)).unwrap();
+
+        assert_eq!(ran.get(), 3);
+        assert_eq!(summary.exit_code(), None);
+        let statuses = summary
+            .tasks()
+            .map(|(_, status)| status.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(statuses, [
+            TaskStatus::Ran,
+            TaskStatus::Partial,
+            TaskStatus::Skipped(SkipReason::StoppedEarly),
+        ]);
+        assert!(summary.is_interesting());
+    }
+
+    #[test]
+    fn captured_output() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            tasks.release.run = [
+                { cmd = "echo abc123", capture = "sha" },
+                "test {{sha}} = abc123",
+                { task = "other" },
+            ]
+            tasks.other.run = "test '{{sha}}' = '{{'sha'}}'"
+            "#,
+        )]);
+        context.root = env::temp_dir();
+
+        let mut plan = context.plan();
+        plan.push(&TaskName::new("/release").relative_to("").unwrap())
+            .unwrap();
+        let commands = RefCell::new(Vec::new());
+        let summary = plan
+            .execute(|entry| commands.borrow_mut().push(entry.command().to_owned()))
+            .unwrap();
+
+        assert_eq!(summary.exit_code(), None);
+        assert_eq!(commands.into_inner(), [
+            "echo abc123",
+            "test abc123 = abc123",
+            "test '{{sha}}' = '{{'sha'}}'",
+        ]);
+    }
+
+    #[test]
+    fn env_overrides() {
+        let project = TestProject::new(&[
+            (
+                PROJECT_FILE,
+                r#"
+                env-files = [".env"]
+                tasks.test.run = "true"
+                "#,
+            ),
+            (".env", "FROM_ENV_FILE=file\nOVERRIDDEN=file"),
+        ]);
+
+        let mut context = project.context();
+        context.set_env("OVERRIDDEN", "cli");
+        context.set_env("FROM_CLI", "cli");
+        let mut plan = context.plan();
+        plan.push(&TaskName::new("/test").relative_to("").unwrap())
+            .unwrap();
+
+        let command = plan.command(&plan.plan[0]).unwrap();
+        assert_eq!(env_var(&command, "FROM_ENV_FILE"), Some(OsStr::new("file")));
+        assert_eq!(env_var(&command, "OVERRIDDEN"), Some(OsStr::new("cli")));
+        assert_eq!(env_var(&command, "FROM_CLI"), Some(OsStr::new("cli")));
+    }
+
+    #[test]
+    fn requires_env() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            tasks.deploy.requires-env = ["WRUN_TEST_REQUIRED", "PATH"]
+            tasks.deploy.run = "true"
+            tasks.all.run = { task = "deploy" }
+            "#,
+        )]);
+        let push = |context: &mut Context| {
+            let mut plan = context.plan();
+            plan.push(&TaskName::new("/all").relative_to("").unwrap())
+                .map(|()| plan.entries().len())
+        };
+
+        let error = push(&mut context).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "missing required environment variable `WRUN_TEST_REQUIRED` for /deploy"
+        );
+
+        context.set_env("WRUN_TEST_REQUIRED", "");
+        assert_eq!(push(&mut context).unwrap(), 1);
+    }
+
+    #[test]
+    fn requires_env_from_task() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            [tasks.env]
+            run = "true"
+            env.WRUN_TEST_DATABASE_URL = "x"
+            requires-env = ["WRUN_TEST_DATABASE_URL"]
+
+            [tasks.matrix]
+            run = "true"
+            matrix.a.WRUN_TEST_DATABASE_URL = "a"
+            matrix.b = {}
+            requires-env = ["WRUN_TEST_DATABASE_URL"]
+            "#,
+        )]);
+        let push = |context: &mut Context, task: &str| {
+            let mut plan = context.plan();
+            plan.push(&TaskName::new(task).relative_to("").unwrap())
+                .map(|()| plan.entries().len())
+        };
+
+        assert_eq!(push(&mut context, "/env").unwrap(), 1);
+        assert_eq!(
+            push(&mut context, "/matrix").unwrap_err().to_string(),
+            "missing required environment variable `WRUN_TEST_DATABASE_URL` for /matrix"
+        );
+    }
+
+    #[test]
+    fn missing_shell() {
+        let mut context =
+            Context::for_test(&[("", r#"tasks.test.run = ["echo one", "echo two"]"#)]);
+        context.root = env::temp_dir();
+        let run = |context: &mut Context| {
+            let mut plan = context.plan();
+            plan.push(&TaskName::new("/test").relative_to("").unwrap())
+                .unwrap();
+            let ran = Cell::new(0);
+            let summary = plan.execute(|_| ran.set(ran.get() + 1));
+            (summary, ran.get())
+        };
+
+        let (summary, ran) = run(&mut context);
+        assert_eq!(summary.unwrap().exit_code(), None);
+        assert_eq!(ran, 2);
+
+        for shell in ["wrun-nonexistent-shell", "/nonexistent/sh"] {
+            context.shell = Shell::Program(vec![shell.to_owned(), "-c".to_owned()]);
+            let (summary, ran) = run(&mut context);
+            assert_eq!(
+                summary.unwrap_err().to_string(),
+                format!("shell `{shell}` was not found")
+            );
+            assert_eq!(ran, 0);
+        }
+    }
+
+    #[test]
+    fn every_package_in_dependency_order() {
+        let mut context = Context::for_test(&[
+            ("", ""),
+            (
+                "app",
+                r#"
+                depends-on = ["../core"]
+                tasks.build.run = "echo app"
+                "#,
+            ),
+            ("docs", r#"tasks.serve.run = "echo docs""#),
+            ("core", r#"tasks.build.run = "echo core""#),
+        ]);
+
+        let mut plan = context.plan();
+        plan.push_in_every_package("build").unwrap();
+        let tasks = plan
+            .entries()
+            .iter()
+            .map(|entry| entry.task().display(Separator::Slash).to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(tasks, ["core/build", "app/build"]);
+
+        let err = plan.push_in_every_package("missing").unwrap_err();
+        assert_eq!(err.to_string(), "no package defines the task `missing`");
+    }
+
+    #[test]
+    fn package_dependency_cycle() {
+        let context = Context::for_test(&[
+            ("", ""),
+            ("a", r#"depends-on = ["../b"]"#),
+            ("b", r#"depends-on = ["../a"]"#),
+        ]);
+
+        let err = context.dependency_order().unwrap_err();
+        assert_eq!(err.to_string(), "package dependency cycle: /a -> /b -> /a");
+    }
+
+    #[test]
+    fn ok_codes() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            tasks.allowed.run = [{ cmd = "exit 1", ok-codes = [1, 2] }, "true"]
+            tasks.other.run = { cmd = "exit 3", ok-codes = [1] }
+            "#,
+        )]);
+        context.root = env::temp_dir();
+
+        let mut plan = context.plan();
+        plan.push(&TaskName::new("/allowed").relative_to("").unwrap())
+            .unwrap();
+        let summary = plan.execute(|_| {}).unwrap();
+        assert_eq!(summary.exit_code(), None);
+
+        let mut plan = context.plan();
+        plan.push(&TaskName::new("/other").relative_to("").unwrap())
+            .unwrap();
+        let summary = plan.execute(|_| {}).unwrap();
+        assert_eq!(summary.exit_code(), Some(3));
+    }
+
+    #[test]
+    fn auto_dotenv() {
+        let project = TestProject::new(&[
+            (
+                PROJECT_FILE,
+                r#"
+                auto-dotenv = true
+                packages = ["pkg"]
+                "#,
+            ),
+            (".env", "FROM_ROOT=root\nOVERRIDDEN=root"),
+            ("pkg/wrun.toml", r#"tasks.test.run = "true""#),
+            ("pkg/.env", "FROM_PACKAGE=pkg\nOVERRIDDEN=pkg"),
+        ]);
+
+        let mut context = Context::from_directory(project.0.join("pkg")).unwrap();
+        let plan = context.plan();
+        let env = plan.env().unwrap();
+        assert_eq!(env["FROM_ROOT"], "root");
+        assert_eq!(env["FROM_PACKAGE"], "pkg");
+        assert_eq!(env["OVERRIDDEN"], "pkg");
+
+        let mut context = project.context();
+        let env = context.plan().env().unwrap();
+        assert_eq!(env["OVERRIDDEN"], "root");
+        assert!(!env.contains_key("FROM_PACKAGE"));
+    }
+
+    #[test]
+    fn cyclic_tasks() {
+        let mut context = Context::for_test(&[
+            (
+                "",
+                r#"
+                tasks.build.run = [{ task = "test" }, { task = "pkg/build" }]
+                tasks.test.run = { task = "build" }
+                tasks.self.run = ["true", { task = "self" }]
+                "#,
+            ),
+            ("pkg", r#"tasks.build.run = "true""#),
+        ]);
+
+        let mut plan = context.plan();
+        let err = plan
+            .push(&TaskName::new("/build").relative_to("").unwrap())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cyclic task dependency: /build -> /test -> /build"
+        );
+
+        let err = plan
+            .push(&TaskName::new("/self").relative_to("").unwrap())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "cyclic task dependency: /self -> /self");
+
+        // Same task name in another package is not a cycle
+        let mut context = Context::for_test(&[
+            ("", r#"tasks.build.run = { task = "pkg/build" }"#),
+            ("pkg", r#"tasks.build.run = "true""#),
+        ]);
+        let mut plan = context.plan();
+        plan.push(&TaskName::new("/build").relative_to("").unwrap())
+            .unwrap();
+        assert_eq!(plan.entries().len(), 1);
+    }
+
+    #[test]
+    fn shared_tasks_run_once() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            tasks.lint.run = [{ task = "codegen" }, { task = "clean" }, "echo lint"]
+            tasks.test.run = [{ task = "codegen" }, { task = "clean" }, "echo test"]
+            tasks.codegen.run = "echo codegen"
+            tasks.clean = { run = "echo clean", repeatable = true }
+            "#,
+        )]);
+
+        let mut plan = context.plan();
+        for task in ["/lint", "/test", "/codegen"] {
+            plan.push(&TaskName::new(task).relative_to("").unwrap())
+                .unwrap();
+        }
+        let commands = plan
+            .entries()
+            .iter()
+            .map(PlanEntry::command)
+            .collect::<Vec<_>>();
+        assert_eq!(commands, [
+            "echo codegen",
+            "echo clean",
+            "echo lint",
+            "echo clean",
+            "echo test",
+        ]);
+    }
+
+    #[test]
+    fn when_command() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            tasks.all.run = [{ task = "docker" }, { task = "shell" }]
+            tasks.docker = { run = "docker build .", when.command = "wrun-test-missing" }
+            tasks.shell = { run = "true", when.command = "sh" }
+            "#,
+        )]);
+        context.root = env::temp_dir();
+
+        let all = TaskName::new("/all").relative_to("").unwrap();
+        let mut plan = context.plan();
+        plan.push(&all).unwrap();
+        let commands = plan
+            .entries()
+            .iter()
+            .map(PlanEntry::command)
+            .collect::<Vec<_>>();
+        assert_eq!(commands, ["true"]);
+
+        let summary = plan.execute(|_| {}).unwrap();
+        let statuses = summary
+            .tasks()
+            .map(|(_, status)| status)
+            .collect::<Vec<_>>();
+        let missing = SkipReason::MissingCommand("wrun-test-missing".to_owned());
+        assert_eq!(statuses, [&TaskStatus::Skipped(missing), &TaskStatus::Ran]);
+        assert_eq!(summary.exit_code(), None);
+    }
+
+    #[test]
+    fn entry_sources() {
+        let project = TestProject::new(&[
+            (
+                PROJECT_FILE,
+                "packages = [\"pkg\"]\n\ntasks.root.run = \"true\"",
+            ),
+            ("base/wrun.toml", "tasks.inherited.run = \"true\""),
+            ("pkg/wrun.toml", "extends = \"../base\""),
+            ("pkg/wrun.d/extra.toml", "\n[tasks.extra]\nrun = \"true\""),
+        ]);
+
+        let mut context = project.context();
+        let mut plan = context.plan();
+        for task in ["pkg/extra", "pkg/inherited", "/root"] {
+            plan.push(&TaskName::new(task).relative_to("").unwrap())
+                .unwrap();
+        }
+        let sources = plan
+            .entries()
+            .iter()
+            .map(|entry| entry.source().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(sources, [
+            (project.0.join("pkg/wrun.d/extra.toml").as_path(), 2),
+            (project.0.join("base/wrun.toml").as_path(), 1),
+            (project.0.join(PROJECT_FILE).as_path(), 3),
+        ]);
+    }
+
+    #[test]
+    fn args() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            tasks.test.run = [{ task = "build" }, "cargo test {{args}}"]
+            tasks.build.run = "cargo build {{args}}"
+            tasks.lint.run = "cargo clippy"
+            "#,
+        )]);
+
+        let mut plan = context.plan();
+        plan.set_args(&["--filter".to_owned(), "my test".to_owned()])
+            .unwrap();
+        plan.push(&TaskName::new("/test").relative_to("").unwrap())
+            .unwrap();
+        let commands = plan
+            .entries()
+            .iter()
+            .map(PlanEntry::command)
+            .collect::<Vec<_>>();
+        assert_eq!(commands, ["cargo build ", "cargo test --filter 'my test'"]);
+
+        let err = plan
+            .push(&TaskName::new("/lint").relative_to("").unwrap())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "/lint does not use {{args}}, so it cannot take arguments"
+        );
+    }
+
+    #[test]
+    fn args_file() {
+        let project = TestProject::new(&[
+            (PROJECT_FILE, r#"tasks.test.run = "cargo test {{args}}""#),
+            (
+                "args.txt",
+                "# Slow tests\n--include-ignored\n\n  --skip  # flaky\nnetwork \
+                 tests\n--color=#fff\n",
+            ),
+        ]);
+
+        let args = read_args_file(&project.0.join("args.txt")).unwrap();
+        assert_eq!(args, [
+            "--include-ignored",
+            "--skip",
+            "network tests",
+            "--color=#fff"
+        ]);
+
+        let mut context = project.context();
+        let mut plan = context.plan();
+        plan.set_args(&args).unwrap();
+        plan.push(&TaskName::new("/test").relative_to("").unwrap())
+            .unwrap();
+        assert_eq!(
+            plan.entries()[0].command(),
+            "cargo test --include-ignored --skip 'network tests' '--color=#fff'"
+        );
+    }
+
+    #[test]
+    fn task_env() {
+        let project = TestProject::new(&[
+            (
+                PROJECT_FILE,
+                r#"
+                env-files = [".env"]
+                tasks.build.run = [{ task = "lint" }, "cargo build"]
+                tasks.lint.run = "true"
+
+                [tasks.build.env]
+                PROFILE = "release"
+                FROM_FILE = "overridden"
+                LOG = "${LOG_LEVEL}-${FROM_FILE}"
+                PATH = "${PATH}:./bin"
+                WRUN_TEST_CLI = "task"
+                UNSET = "[${WRUN_TEST_UNSET}]"
+                "#,
+            ),
+            (".env", "FROM_FILE=env-file\nLOG_LEVEL=debug"),
+        ]);
+
+        let mut context = project.context();
+        context.set_env("WRUN_TEST_CLI", "cli");
+        let mut plan = context.plan();
+        plan.push(&TaskName::new("/build").relative_to("").unwrap())
+            .unwrap();
+        let [lint, build] = plan.entries() else {
+            panic!("expected two commands");
+        };
+
+        let env = plan.entry_env(build).unwrap();
+        assert_eq!(env["PROFILE"], "release");
+        assert_eq!(env["FROM_FILE"], "overridden");
+        assert_eq!(env["LOG"], "debug-env-file");
+        assert_eq!(env["UNSET"], "[]");
+        assert_eq!(env["WRUN_TEST_CLI"], "cli");
+
+        let mut path = env::var_os("PATH").unwrap();
+        path.push(":./bin");
+        assert_eq!(env["PATH"], path);
+
+        let env = plan.entry_env(lint).unwrap();
+        assert_eq!(env["FROM_FILE"], "env-file");
+        assert!(!env.contains_key("PROFILE"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn shells() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            shell = ["sh", "-euc"]
+            tasks.strict.run = 'test -z "$WRUN_TEST_UNSET"'
+            tasks.lenient = { run = 'test -z "$WRUN_TEST_UNSET"', shell = ["sh", "-c"] }
+            tasks.direct = { run = "test 'a b' = \"a b\"", shell = false }
+            tasks.unbalanced = { run = "echo 'oops", shell = false }
+            "#,
+        )]);
+        context.root = env::temp_dir();
+        let mut run = |task: &str| {
+            let mut plan = context.plan();
+            plan.push(&TaskName::new(task).relative_to("").unwrap())
+                .unwrap();
+            plan.execute(|_| {}).map(|summary| summary.exit_code())
+        };
+
+        assert_ne!(run("/strict").unwrap(), None);
+        assert_eq!(run("/lenient").unwrap(), None);
+        assert_eq!(run("/direct").unwrap(), None);
+        assert_eq!(
+            run("/unbalanced").unwrap_err().to_string(),
+            "cannot split `echo 'oops` into words to run without a shell"
+        );
+    }
+
+    #[test]
+    fn package_without_shell() {
+        fn plan<'a>(context: &'a mut Context, task: &str) -> anyhow::Result<Plan<'a>> {
+            let mut plan = context.plan();
+            plan.push(&TaskName::new(task).relative_to("").unwrap())?;
+            Ok(plan)
+        }
+
+        let mut context = Context::for_test(&[
+            ("", r#"tasks.piped.run = "ls | wc -l""#),
+            (
+                "pkg",
+                r#"
+                shell = false
+                tasks.direct.run = "test 'a | b' = \"a | b\""
+                tasks.piped.run = "ls | wc -l"
+                tasks.quoted.run = "echo \"$HOME\""
+                tasks.shell = { run = "ls | wc -l", shell = ["sh", "-c"] }
+                "#,
+            ),
+        ]);
+
+        let direct = plan(&mut context, "pkg/direct").unwrap();
+        let command = direct.command(&direct.entries()[0]).unwrap();
+        assert_eq!(command.get_program(), "test");
+        let args = command.get_args().collect::<Vec<_>>();
+        assert_eq!(args, ["a | b", "=", "a | b"]);
+
+        for (task, command, c) in [
+            ("pkg/piped", "ls | wc -l", '|'),
+            ("pkg/quoted", "echo \"$HOME\"", '$'),
+        ] {
+            assert_eq!(
+                plan(&mut context, task).err().unwrap().to_string(),
+                format!("{task} runs `{command}` without a shell, so it cannot use `{c}`")
+            );
+        }
+
+        let sh = Shell::Program(vec!["sh".to_owned(), "-c".to_owned()]);
+        for (task, shell) in [("pkg/shell", sh), ("/piped", Shell::Default)] {
+            let plan = plan(&mut context, task).unwrap();
+            assert_eq!(plan.entries()[0].shell, shell);
+        }
+    }
 }
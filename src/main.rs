@@ -1,17 +1,23 @@
 mod cli;
 
-use std::{env, fs};
+use std::cell::{Cell, RefCell};
+use std::io::{self, BufRead, IsTerminal as _, Write};
+use std::path::Path;
+use std::process::{self, Command};
+use std::time::Duration;
+use std::{env, fmt, fs};
 
-use anyhow::bail;
+use anyhow::{Context as _, bail};
 use owo_colors::{OwoColorize as _, Stream};
-use wrun::{Task, TaskName};
+use wrun::{EnvExplanation, Separator, Summary, Task, TaskDiff, TaskName, TaskStatus, TaskTree};
 
-use self::cli::Action;
+use self::cli::{Action, Args, Sort};
 
 fn main() -> anyhow::Result<()> {
     let args = cli::parse();
 
     let directory = if let Some(dir) = &args.directory {
+        let dir = wrun::expand_home(dir);
         if !dir.is_dir() {
             bail!("{} is not a directory", dir.to_string_lossy());
         }
@@ -21,64 +27,777 @@ fn main() -> anyhow::Result<()> {
         env::current_dir()?
     };
 
-    let context = wrun::Context::from_directory(directory)?;
+    let mut context = wrun::Context::from_directory(&directory)?;
+    if args.no_env_files {
+        context.disable_env_files();
+    }
+    if args.pty {
+        context.force_pty();
+    }
+    if let Some(max) = args.max_output_lines {
+        context.limit_output(max);
+    }
+    if args.summary_only {
+        context.quiet();
+    }
+    if let Some(lines) = args.stderr_tail {
+        context.keep_stderr_tail(lines);
+    }
+    if let Some(seconds) = args.max_time {
+        context.limit_time(Duration::from_secs(seconds));
+    }
+    if let Some(commands) = args.stop_after {
+        context.stop_after(commands);
+    }
+    if let Some(jobs) = args.jobs {
+        context.set_jobs(jobs);
+    }
+    for (key, value) in &args.env {
+        context.set_env(key, value);
+    }
+    if let Some(profile) = &args.profile {
+        context.set_profile(profile);
+    }
+
+    for warning in context.warnings() {
+        print_warning(warning);
+    }
 
     match args.action() {
-        Action::List { all } => list_tasks(&context, all),
-        Action::Run(tasks) => execute_tasks(context, tasks)?,
+        Action::List { all, tree } if args.require_descriptions => {
+            let filter = Filter::Undocumented;
+            let listed = list_tasks(&mut io::stdout(), &context, all, tree, args.sort, filter)?;
+            if listed > 0 {
+                bail!("{listed} public tasks do not have a description");
+            }
+        }
+        Action::List { all, tree } => {
+            let filter = if args.undocumented {
+                Filter::Undocumented
+            } else {
+                Filter::Public
+            };
+            list_tasks(&mut io::stdout(), &context, all, tree, args.sort, filter)?;
+        }
+        Action::Run(tasks) => execute_tasks(context, tasks, &args)?,
+        Action::Pick => {
+            let candidates = pick_candidates(&context);
+            let is_terminal = io::stdin().is_terminal() && io::stderr().is_terminal();
+            let picked = pick_task(
+                &candidates,
+                io::stdin().lock(),
+                &mut io::stderr(),
+                is_terminal,
+            )?;
+            match picked {
+                Picked::Task(task) => execute_tasks(context, &[task], &args)?,
+                Picked::Nothing => {}
+                Picked::NotInteractive => {
+                    let out = &mut io::stdout();
+                    list_tasks(out, &context, false, false, args.sort, Filter::Public)?;
+                }
+            }
+        }
+        Action::Exists(task) => {
+            let local = context.local_package_name().to_owned();
+            let task = TaskName::parse(task, context.separator())?.relative_to(local)?;
+            if !context.has_task(&task) {
+                process::exit(1);
+            }
+        }
+        Action::Edit(task) => {
+            let local = context.local_package_name().to_owned();
+            let task = TaskName::parse(task, context.separator())?.relative_to(local)?;
+            let (path, line) = context.task_source(&task)?;
+
+            let editor = env::var("EDITOR").ok();
+            if let Some(mut command) = editor_command(editor.as_deref(), &path, line) {
+                let status = command.status().context("failed to start $EDITOR")?;
+                if !status.success() {
+                    bail!("$EDITOR exited with {status}");
+                }
+            } else {
+                println!("{}:{line}", path.display());
+            }
+        }
+        Action::DumpMakefile => print!("{}", context.to_makefile()?),
+        Action::DumpGraphJson => println!("{}", context.graph()?.to_json()),
+        Action::DebugCompletions(prefix) => {
+            let candidates = cli::completion_candidates(&directory, prefix.as_ref());
+            for candidate in candidates {
+                let value = candidate.get_value().to_string_lossy();
+                if !value.starts_with(prefix) {
+                    continue;
+                }
+
+                let help = candidate.get_help().map(|help| help.to_string());
+                let hidden = if candidate.is_hide_set() {
+                    " (hidden)"
+                } else {
+                    ""
+                };
+                println!("{value}\t{}{hidden}", help.unwrap_or_default());
+            }
+        }
+        Action::DiffTasks(git_ref) => {
+            print_task_diffs(&mut io::stdout(), &context.diff_tasks(git_ref)?)?
+        }
+        Action::ExplainEnv(key) => {
+            let task = match &args.for_task {
+                Some(task) => {
+                    let local = context.local_package_name().to_owned();
+                    Some(TaskName::parse(task, context.separator())?.relative_to(local)?)
+                }
+                None => None,
+            };
+            let explained = context.plan().explain_env(key, task.as_ref())?;
+            print_env_explanation(&mut io::stdout(), key, &explained, args.mask)?;
+        }
+        Action::Version if args.json => println!("{}", version_json()),
         Action::Version => println!("{}", env!("CARGO_PKG_VERSION")),
     }
 
     Ok(())
 }
 
-fn list_tasks(context: &wrun::Context, all: bool) {
-    let is_public = |t: &(_, &Task)| !t.1.is_internal();
-    let print_task = |name: &str, task: &Task| {
-        let name = name.if_supports_color(Stream::Stdout, |s| s.purple());
-        println!("  {name:18}  {}", task.description().unwrap_or_default())
+/// The command opening `path` at `line` in `editor`, which is split into words
+/// here rather than by a shell, since that may not be `sh`
+fn editor_command(editor: Option<&str>, path: &Path, line: usize) -> Option<Command> {
+    let words = shlex::split(editor?)?;
+    let (program, args) = words.split_first()?;
+    let mut command = Command::new(program);
+    command.args(args).arg(format!("+{line}")).arg(path);
+    Some(command)
+}
+
+/// Which tasks to list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Filter {
+    Public,
+    /// Public tasks without a description
+    Undocumented,
+}
+
+/// Returns how many tasks were listed
+fn list_tasks(
+    out: &mut impl Write,
+    context: &wrun::Context,
+    all: bool,
+    tree: bool,
+    sort: Sort,
+    filter: Filter,
+) -> anyhow::Result<usize> {
+    let is_listed = |t: &(_, &Task)| {
+        let undocumented =
+            t.1.description()
+                .is_none_or(|description| description.is_empty());
+        !t.1.is_internal() && (filter == Filter::Public || undocumented)
+    };
+    let listed = Cell::new(0);
+    let print_task = |out: &mut dyn Write, package: &str, name: &str, task: &Task| {
+        let colored = name.if_supports_color(Stream::Stdout, |s| s.purple());
+        let absolute = TaskName::Local(name.to_owned()).relative_to(package)?;
+        let (description, warnings) = context.description(&absolute, task).unwrap_or_default();
+        for warning in warnings {
+            print_warning(&warning);
+        }
+        if task.deprecated().is_some() {
+            let deprecated = "(deprecated)".if_supports_color(Stream::Stdout, |s| s.dimmed());
+            writeln!(out, "  {colored:18}  {description} {deprecated}")?;
+        } else {
+            writeln!(out, "  {colored:18}  {description}")?;
+        }
+
+        if tree {
+            print_subtasks(out, &context.tree(&absolute)?, 2, context.separator())?;
+        }
+        listed.set(listed.get() + 1);
+        anyhow::Ok(())
     };
 
-    println!("Local:");
+    writeln!(out, "Local:")?;
 
-    for (name, task) in context.local_tasks().iter().filter(is_public) {
-        print_task(name, task);
+    let local = context.local_package_name();
+    for (name, task) in sorted(context.local_tasks().iter().filter(is_listed), sort) {
+        print_task(out, local, name, task)?;
     }
 
     if all {
-        let local = context.local_package_name();
-        for (name, package) in context.packages() {
-            if name == local {
+        for (package_name, package) in context.packages() {
+            if package_name == local || package.tasks().is_empty() {
                 continue;
             }
 
-            let mut tasks = package.tasks().iter().filter(is_public).peekable();
-            if tasks.peek().is_some() {
-                let name = &format!("{name}/");
-                let name = name.if_supports_color(Stream::Stdout, |s| s.blue());
-                println!("In {name}:");
+            let tasks = sorted(package.tasks().iter().filter(is_listed), sort);
+            if !tasks.is_empty() {
+                if package_name.is_empty() {
+                    writeln!(out, "Root:")?;
+                } else {
+                    let name = &format!("{package_name}{}", context.separator());
+                    let name = name.if_supports_color(Stream::Stdout, |s| s.blue());
+                    writeln!(out, "In {name}:")?;
+                }
                 for (name, task) in tasks {
-                    print_task(name, task);
+                    print_task(out, package_name, name, task)?;
                 }
             }
         }
     }
+
+    Ok(listed.get())
 }
 
-fn execute_tasks(mut context: wrun::Context, tasks: &[String]) -> anyhow::Result<()> {
+/// Print `command` before running it to whichever of `stdout` and `stderr`
+/// `stream` selects
+fn echo(
+    stream: Stream,
+    task: impl fmt::Display,
+    command: &str,
+    stdout: &mut impl Write,
+    stderr: &mut impl Write,
+) -> io::Result<()> {
+    let task = task.if_supports_color(stream, |s| s.purple());
+    let out: &mut dyn Write = match stream {
+        Stream::Stdout => stdout,
+        _ => stderr,
+    };
+    writeln!(out, "wrun({task}): {command}")
+}
+
+/// Every public task with its description, local ones first
+fn pick_candidates(context: &wrun::Context) -> Vec<(String, String)> {
+    let local_package = context.local_package_name();
+    let (mut local, mut others) = (Vec::new(), Vec::new());
+    for (package, tasks) in context.packages() {
+        for (name, task) in tasks.tasks().iter().filter(|(_, task)| !task.is_internal()) {
+            let description = task.description().unwrap_or_default().to_owned();
+            if package == local_package {
+                local.push((name.to_owned(), description));
+            } else {
+                let name = TaskName::Local(name.to_owned())
+                    .relative_to(package)
+                    .map(|name| name.display(context.separator()).to_string())
+                    .unwrap_or_default();
+                others.push((name, description));
+            }
+        }
+    }
+    local.append(&mut others);
+    local
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Picked {
+    Task(String),
+    /// The prompt was left empty or closed
+    Nothing,
+    NotInteractive,
+}
+
+/// Prompt for one of `candidates` by number or name
+fn pick_task(
+    candidates: &[(String, String)],
+    mut input: impl BufRead,
+    output: &mut impl Write,
+    is_terminal: bool,
+) -> io::Result<Picked> {
+    if !is_terminal {
+        return Ok(Picked::NotInteractive);
+    }
+
+    let width = candidates.len().to_string().len();
+    for (i, (name, description)) in candidates.iter().enumerate() {
+        writeln!(output, "{:>width$}) {name:18}  {description}", i + 1)?;
+    }
+
+    loop {
+        write!(output, "Task to run: ")?;
+        output.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(Picked::Nothing);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(Picked::Nothing);
+        }
+
+        let by_number = line
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| candidates.get(n.checked_sub(1)?));
+        let by_name = candidates.iter().find(|(name, _)| name == line);
+        match by_number.or(by_name) {
+            Some((name, _)) => return Ok(Picked::Task(name.clone())),
+            None => writeln!(output, "No task `{line}`")?,
+        }
+    }
+}
+
+fn sorted<'a>(
+    tasks: impl Iterator<Item = (&'a str, &'a Task)>,
+    sort: Sort,
+) -> Vec<(&'a str, &'a Task)> {
+    let mut tasks = tasks.collect::<Vec<_>>();
+    match sort {
+        Sort::File => {}
+        Sort::Name => tasks.sort_by_key(|(name, _)| *name),
+        Sort::Reverse => tasks.reverse(),
+    }
+    tasks
+}
+
+fn print_subtasks(
+    out: &mut dyn Write,
+    tree: &TaskTree,
+    depth: usize,
+    separator: Separator,
+) -> io::Result<()> {
+    let indent = "  ".repeat(depth);
+    for child in tree.children() {
+        let name = child.name().display(separator);
+        let name = name.if_supports_color(Stream::Stdout, |s| s.purple());
+        if child.is_cycle() {
+            writeln!(out, "{indent}{name} (cycle)")?;
+        } else {
+            writeln!(out, "{indent}{name}")?;
+            print_subtasks(out, child, depth + 1, separator)?;
+        }
+    }
+    Ok(())
+}
+
+fn execute_tasks(mut context: wrun::Context, tasks: &[String], args: &Args) -> anyhow::Result<()> {
     let local_package = context.local_package_name().to_owned();
-    let abs_task = |task| TaskName::new(task).relative_to(&local_package);
+    let separator = context.separator();
+    let abs_task = |task: &str| {
+        TaskName::parse(task, separator)
+            .with_context(|| format!("invalid task `{task}`"))?
+            .relative_to(&local_package)
+    };
+    let names = if args.all_packages {
+        Vec::new()
+    } else {
+        tasks
+            .iter()
+            .map(|task| abs_task(task))
+            .collect::<anyhow::Result<Vec<_>>>()?
+    };
+
     let mut plan = context.plan();
-    for task in tasks {
-        plan.push(&abs_task(task))?;
+    if !args.no_prelude {
+        plan.push_prelude(&names)?;
+    }
+    let mut task_args = match &args.args_file {
+        Some(path) => wrun::read_args_file(&wrun::expand_home(path))?,
+        None => Vec::new(),
+    };
+    task_args.extend(args.args.iter().cloned());
+    if !task_args.is_empty() {
+        plan.set_args(&task_args)?;
+    }
+    if args.all_packages {
+        for task in tasks {
+            match TaskName::parse(task, separator) {
+                Ok(TaskName::Local(task)) => plan.push_in_every_package(&task)?,
+                _ => bail!("--all-packages takes task names without a package, not `{task}`"),
+            }
+        }
+    }
+    for task in &names {
+        plan.push(task)?;
+    }
+
+    for warning in plan.warnings() {
+        print_warning(warning);
+    }
+
+    if args.plan_hash {
+        println!("{}", plan.hash()?);
+        return Ok(());
+    }
+
+    if args.to_script {
+        print!("{}", plan.to_script()?);
+        return Ok(());
+    }
+
+    if plan.is_empty() {
+        for (task, reason) in plan.skipped() {
+            eprintln!("skipped {} ({reason})", task.display(separator));
+        }
+        if args.fail_on_empty {
+            bail!("nothing to run");
+        }
+        eprintln!("nothing to run");
+        return Ok(());
+    }
+
+    if args.dry_run {
+        for (task, reason) in plan.skipped() {
+            let task = task.display(separator);
+            let task = task.if_supports_color(Stream::Stdout, |s| s.purple());
+            println!("wrun({task}): skipped ({reason})");
+        }
+        for entry in plan.entries() {
+            let task = entry.task().display(separator);
+            let task = task.if_supports_color(Stream::Stdout, |s| s.purple());
+            println!("wrun({task}): {}", entry.command());
+
+            if args.verbose {
+                if let Some((path, line)) = entry.source() {
+                    println!("    from {}:{line}", path.display());
+                }
+                for (key, value) in plan.entry_env(entry)? {
+                    if args.show_env_values {
+                        println!("    {key}={}", value.to_string_lossy());
+                    } else {
+                        println!("    {key}");
+                    }
+                }
+            }
+        }
+        return Ok(());
     }
-    plan.execute(|entry| {
-        if !entry.silent() {
-            let task = entry.task();
-            let task = task.if_supports_color(Stream::Stderr, |s| s.purple());
-            eprintln!("wrun({task}): {}", entry.command());
+
+    let stream = if args.echo_stdout {
+        Stream::Stdout
+    } else {
+        Stream::Stderr
+    };
+    let groups = match args.ci_groups {
+        Some(Some(groups)) => Some(groups),
+        Some(None) => wrun::CiGroups::detect(),
+        None => None,
+    };
+    let grouper = RefCell::new(groups.map(|groups| wrun::Grouper::new(groups, separator)));
+
+    let summary = plan.execute(|entry| {
+        if let Some(grouper) = &mut *grouper.borrow_mut() {
+            print!("{}", grouper.enter(entry.group()));
+            let _ = io::stdout().flush();
+        }
+
+        if !entry.silent() && !args.summary_only {
+            let (mut stdout, mut stderr) = (io::stdout().lock(), io::stderr().lock());
+            let _ = echo(
+                stream,
+                entry.task().display(separator),
+                entry.command(),
+                &mut stdout,
+                &mut stderr,
+            );
         }
     })?;
 
+    if let Some(grouper) = &mut *grouper.borrow_mut() {
+        print!("{}", grouper.finish());
+    }
+
+    if summary.is_interesting() || args.summary_only {
+        print_summary(&summary, separator);
+    }
+
+    if let Some(code) = summary.exit_code() {
+        process::exit(code);
+    }
+
     Ok(())
 }
+
+fn print_summary(summary: &Summary, separator: Separator) {
+    eprintln!("\nSummary:");
+    for (task, status) in summary.tasks() {
+        let task = task.display(separator);
+        let task = task.if_supports_color(Stream::Stderr, |s| s.purple());
+        match status {
+            TaskStatus::Ran => {
+                let status = "ran".if_supports_color(Stream::Stderr, |s| s.green());
+                eprintln!("  {status:7}  {task}");
+            }
+            TaskStatus::Skipped(reason) => {
+                let status = "skipped".if_supports_color(Stream::Stderr, |s| s.yellow());
+                eprintln!("  {status:7}  {task} ({reason})");
+            }
+            TaskStatus::Failed { code } => {
+                let status = "failed".if_supports_color(Stream::Stderr, |s| s.red());
+                eprintln!("  {status:7}  {task} (exit code {code})");
+            }
+            TaskStatus::TimedOut => {
+                let status = "timeout".if_supports_color(Stream::Stderr, |s| s.red());
+                eprintln!("  {status:7}  {task} (ran past --max-time)");
+            }
+            TaskStatus::Partial => {
+                let status = "partial".if_supports_color(Stream::Stderr, |s| s.yellow());
+                eprintln!("  {status:7}  {task} (stopped by --stop-after)");
+            }
+            TaskStatus::Cancelled => {
+                let status = "stopped".if_supports_color(Stream::Stderr, |s| s.red());
+                eprintln!("  {status:7}  {task} (another task failed)");
+            }
+        }
+    }
+
+    if let Some((task, tail)) = summary.stderr_tail() {
+        let task = task.display(separator);
+        let task = task.if_supports_color(Stream::Stderr, |s| s.purple());
+        eprintln!("\nEnd of stderr from {task}:");
+        for line in tail {
+            eprintln!("  {line}");
+        }
+    }
+}
+
+fn version_json() -> serde_json::Value {
+    serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "target": env!("TARGET"),
+        "os": env::consts::OS,
+        "arch": env::consts::ARCH,
+    })
+}
+
+fn print_env_explanation(
+    out: &mut impl Write,
+    key: &str,
+    explained: &EnvExplanation,
+    mask: bool,
+) -> io::Result<()> {
+    let show = |value: &str| {
+        if mask {
+            "***".to_owned()
+        } else {
+            value.to_owned()
+        }
+    };
+
+    let Some(value) = explained.value() else {
+        return writeln!(out, "{key} is not set");
+    };
+
+    writeln!(out, "{key} is set by, from lowest to highest precedence:")?;
+    for (source, value) in explained.sources() {
+        writeln!(out, "  {source}: {}", show(value))?;
+    }
+    writeln!(out, "Final value: {}", show(&value.to_string_lossy()))
+}
+
+fn print_task_diffs(out: &mut impl Write, diffs: &[(String, TaskDiff)]) -> io::Result<()> {
+    if diffs.is_empty() {
+        return writeln!(out, "No tasks changed");
+    }
+
+    for (i, (package, diff)) in diffs.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        match package.as_str() {
+            "" => writeln!(out, "Root:")?,
+            package => writeln!(out, "{package}:")?,
+        }
+
+        let kinds = [
+            ("+", diff.added()),
+            ("-", diff.removed()),
+            ("~", diff.changed()),
+        ];
+        for (marker, tasks) in kinds {
+            for task in tasks {
+                writeln!(out, "  {marker} {task}")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_warning(warning: &str) {
+    let prefix = "warning:".if_supports_color(Stream::Stderr, |s| s.yellow());
+    eprintln!("{prefix} {warning}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_stream() {
+        let task = wrun::AbsoluteTaskName::Root("build".to_owned());
+        let echoed = |stream| {
+            let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+            echo(
+                stream,
+                task.display(Separator::Slash),
+                "make",
+                &mut stdout,
+                &mut stderr,
+            )
+            .unwrap();
+            (
+                String::from_utf8(stdout).unwrap(),
+                String::from_utf8(stderr).unwrap(),
+            )
+        };
+
+        let line = "wrun(/build): make\n".to_owned();
+        assert_eq!(echoed(Stream::Stdout), (line.clone(), String::new()));
+        assert_eq!(echoed(Stream::Stderr), (String::new(), line));
+    }
+
+    #[test]
+    fn sort_orders() {
+        let task = Task::default();
+        let tasks = [("lint", &task), ("build", &task), ("test", &task)];
+        let names = |sort| {
+            sorted(tasks.into_iter(), sort)
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names(Sort::default()), ["lint", "build", "test"]);
+        assert_eq!(names(Sort::Name), ["build", "lint", "test"]);
+        assert_eq!(names(Sort::Reverse), ["test", "build", "lint"]);
+    }
+
+    #[test]
+    fn env_args() {
+        use clap::Parser as _;
+
+        let args = Args::try_parse_from(["wrun", "-e", "A=1", "--env", "B=x=y", "test"]).unwrap();
+        assert_eq!(args.env, [
+            ("A".to_owned(), "1".to_owned()),
+            ("B".to_owned(), "x=y".to_owned())
+        ]);
+
+        assert!(Args::try_parse_from(["wrun", "-e", "A", "test"]).is_err());
+        assert!(Args::try_parse_from(["wrun", "-e", "=1", "test"]).is_err());
+    }
+
+    #[test]
+    fn root_section() {
+        let root = env::temp_dir().join(format!("wrun-main-test-{}", process::id()));
+        let files = [
+            (
+                "wrun-project.toml",
+                "packages = [\"pkg\", \"other\"]\ntasks.root-task.run = \"true\"",
+            ),
+            ("pkg/wrun.toml", "tasks.local-task.run = \"true\""),
+            ("other/wrun.toml", "tasks.other-task.run = \"true\""),
+        ];
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        let list = |dir: &str| {
+            let context = wrun::Context::from_directory(root.join(dir)).unwrap();
+            let mut out = Vec::new();
+            list_tasks(&mut out, &context, true, false, Sort::File, Filter::Public).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let from_package = list("pkg");
+        let from_root = list("");
+        let project = format!("separator = \"::\"\n{}", files[0].1);
+        fs::write(root.join(files[0].0), project).unwrap();
+        let separated = list("");
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            from_package,
+            "Local:\n  local-task          \nRoot:\n  root-task           \nIn other/:\n  \
+             other-task          \n"
+        );
+        assert_eq!(
+            from_root,
+            "Local:\n  root-task           \nIn pkg/:\n  local-task          \nIn other/:\n  \
+             other-task          \n"
+        );
+        assert!(separated.contains("\nIn pkg:::\n"), "{separated}");
+    }
+
+    #[test]
+    fn undocumented_tasks() {
+        let root = env::temp_dir().join(format!("wrun-undocumented-test-{}", process::id()));
+        let project = r#"
+            tasks.documented = { run = "true", description = "Does things" }
+            tasks.undocumented.run = "true"
+            tasks.empty = { run = "true", description = "" }
+            tasks.helper = { run = "true", internal = true }
+        "#;
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("wrun-project.toml"), project).unwrap();
+
+        let context = wrun::Context::from_directory(&root).unwrap();
+        let mut out = Vec::new();
+        let listed = list_tasks(
+            &mut out,
+            &context,
+            true,
+            false,
+            Sort::Name,
+            Filter::Undocumented,
+        );
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(listed.unwrap(), 2);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Local:\n  empty               \n  undocumented        \n"
+        );
+    }
+
+    fn candidates() -> Vec<(String, String)> {
+        vec![
+            ("build".to_owned(), "Build it".to_owned()),
+            ("pkg/test".to_owned(), String::new()),
+        ]
+    }
+
+    #[test]
+    fn pick_by_number_or_name() {
+        let pick = |input: &str| {
+            let mut output = Vec::new();
+            pick_task(&candidates(), input.as_bytes(), &mut output, true).unwrap()
+        };
+
+        assert_eq!(pick("2\n"), Picked::Task("pkg/test".to_owned()));
+        assert_eq!(pick("build\n"), Picked::Task("build".to_owned()));
+        assert_eq!(pick("nope\n0\n1\n"), Picked::Task("build".to_owned()));
+        assert_eq!(pick("\n"), Picked::Nothing);
+        assert_eq!(pick(""), Picked::Nothing);
+    }
+
+    #[test]
+    fn pick_outside_terminal() {
+        let mut output = Vec::new();
+        let picked = pick_task(&candidates(), "1\n".as_bytes(), &mut output, false).unwrap();
+        assert_eq!(picked, Picked::NotInteractive);
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn version_json() {
+        let version = super::version_json();
+        assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+        assert!(!version["target"].as_str().unwrap().is_empty());
+        assert_eq!(version["os"], env::consts::OS);
+    }
+
+    #[test]
+    fn editor() {
+        let path = Path::new("my dir/wrun.toml");
+        let command = editor_command(Some("code --wait"), path, 3).unwrap();
+        assert_eq!(command.get_program(), "code");
+        let args = command.get_args().collect::<Vec<_>>();
+        assert_eq!(args, ["--wait", "+3", "my dir/wrun.toml"]);
+
+        let command = editor_command(Some("'/opt/my editor/bin/ed'"), path, 1).unwrap();
+        assert_eq!(command.get_program(), "/opt/my editor/bin/ed");
+
+        for editor in [None, Some(""), Some("  "), Some("'unterminated")] {
+            assert!(editor_command(editor, path, 1).is_none(), "{editor:?}");
+        }
+    }
+}
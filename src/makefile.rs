@@ -0,0 +1,354 @@
+//! One-way export of a project's tasks as a Makefile
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use anyhow::bail;
+
+use crate::data::{Package, Run, Task};
+use crate::{AbsoluteTaskName, Context, Shell, braces, template};
+
+impl Context {
+    /// Export every task in the project as a phony target, with the tasks it
+    /// runs as prerequisites and its commands as the recipe, one recipe line
+    /// per line of each command. Silent commands are prefixed with `@`.
+    /// Prerequisites that would form a cycle are left out.
+    ///
+    /// Prerequisites always run before the recipe, so commands that would
+    /// have run between other tasks run after all of them instead. Tasks
+    /// using options make cannot reproduce, like `env`, `matrix`, `retry`, or
+    /// `{{args}}` and other placeholders, are an error.
+    pub fn to_makefile(&self) -> anyhow::Result<String> {
+        let mut makefile = Makefile {
+            context: self,
+            out: String::from("# Generated by `wrun --dump-makefile`\n"),
+            stack: Vec::new(),
+            done: Vec::new(),
+            unlisted: HashMap::new(),
+        };
+
+        for (package, tasks) in self.packages.iter() {
+            for (task, _) in tasks.tasks.iter() {
+                let name = crate::TaskName::Local(task.to_owned()).relative_to(package)?;
+                makefile.visit(&name)?;
+            }
+        }
+
+        Ok(makefile.out)
+    }
+}
+
+struct Makefile<'a> {
+    context: &'a Context,
+    out: String,
+    stack: Vec<AbsoluteTaskName>,
+    done: Vec<AbsoluteTaskName>,
+    unlisted: HashMap<String, Package>,
+}
+
+impl Makefile<'_> {
+    fn visit(&mut self, name: &AbsoluteTaskName) -> anyhow::Result<()> {
+        if self.done.contains(name) || self.stack.contains(name) {
+            return Ok(());
+        }
+
+        let task = self.context.find_task(name, &mut self.unlisted)?;
+        if let Some(option) = self.unsupported(&task) {
+            bail!(
+                "cannot export {} to a Makefile, since it uses `{option}`",
+                name.display(self.context.separator),
+            );
+        }
+        let runs = task.run_for(self.context.profile.as_deref());
+        if let Some(placeholder) = placeholder(runs) {
+            bail!(
+                "cannot export {} to a Makefile, since it uses `{{{{{placeholder}}}}}`",
+                name.display(self.context.separator),
+            );
+        }
+        self.stack.push(name.clone());
+
+        let mut prerequisites = Vec::new();
+        for run in runs {
+            if let Run::Task(child) = run {
+                let child = child.clone().relative_to(name.package())?;
+                if !self.stack.contains(&child) {
+                    self.visit(&child)?;
+                    prerequisites.push(target(&child));
+                }
+            }
+        }
+
+        self.stack.pop();
+        self.done.push(name.clone());
+
+        let target = target(name);
+        let out = &mut self.out;
+        writeln!(out, "\n.PHONY: {target}")?;
+        writeln!(
+            out,
+            "{target}:{}",
+            prerequisites
+                .iter()
+                .map(|p| format!(" {p}"))
+                .collect::<String>()
+        )?;
+        for run in runs {
+            if let Run::Command {
+                command, silent, ..
+            } = run
+            {
+                let silent = silent.unwrap_or(task.silent()).resolve(self.context.ci);
+                let prefix = if silent { "@" } else { "" };
+                let cd = match name.package() {
+                    "" => String::new(),
+                    package => format!("cd {} && ", shlex::try_quote(package)?).replace('$', "$$"),
+                };
+                let command = if task.expands_braces() {
+                    braces::expand(command)
+                } else {
+                    command.clone()
+                };
+                // Each line is its own recipe line, run in a new shell,
+                // unless the line before it continues onto it
+                let mut continued = false;
+                for line in command.lines().filter(|line| !line.trim().is_empty()) {
+                    let line = line.replace('$', "$$");
+                    if continued {
+                        writeln!(out, "\t{line}")?;
+                    } else {
+                        writeln!(out, "\t{prefix}{cd}{line}")?;
+                    }
+                    continued = line.ends_with('\\');
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The first option `task` sets that the Makefile would ignore
+    fn unsupported(&self, task: &Task) -> Option<&'static str> {
+        let shell = task.shell().unwrap_or(&self.context.shell);
+        let runs = task.run_for(self.context.profile.as_deref());
+        let command_uses = |check: fn(&Run) -> bool| runs.iter().any(check);
+
+        if task.for_each().is_some() {
+            Some("for-each")
+        } else if task.matrix().next().is_some() {
+            Some("matrix")
+        } else if task.env().next().is_some() {
+            Some("env")
+        } else if !task.requires_env().is_empty() {
+            Some("requires-env")
+        } else if task.when().is_some_and(|when| when.command.is_some()) {
+            Some("when.command")
+        } else if task.retry().is_some() {
+            Some("retry")
+        } else if task.uses_pty() {
+            Some("pty")
+        } else if task.nice().is_some() {
+            Some("nice")
+        } else if task.is_repeatable() {
+            Some("repeatable")
+        } else if *shell != Shell::Default {
+            Some("shell")
+        } else if command_uses(|run| {
+            matches!(run, Run::Command {
+                capture: Some(_),
+                ..
+            })
+        }) {
+            Some("capture")
+        } else if command_uses(
+            |run| matches!(run, Run::Command { ok_codes, .. } if !ok_codes.is_empty()),
+        ) {
+            Some("ok-codes")
+        } else {
+            None
+        }
+    }
+}
+
+/// The first `{{name}}` placeholder in any of `runs`' commands, which make
+/// would run as written
+fn placeholder(runs: &[Run]) -> Option<String> {
+    runs.iter().find_map(|run| match run {
+        Run::Command { command, .. } => template::render(command, |_| None).1.into_iter().next(),
+        Run::Task(_) => None,
+    })
+}
+
+/// Target names always use `/`, since the other separators mean something to
+/// make
+fn target(name: &AbsoluteTaskName) -> String {
+    match name.package() {
+        "" => name.task().to_owned(),
+        package => format!("{package}/{}", name.task()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+
+    use super::*;
+    use crate::PROJECT_FILE;
+    use crate::tests::TestProject;
+
+    #[test]
+    fn targets_and_prerequisites() {
+        let context = Context::for_test(&[
+            (
+                "",
+                r#"
+                tasks.all.run = [{ task = "lint" }, { task = "pkg/build" }, "@echo done"]
+                tasks.lint.run = "cargo clippy"
+                "#,
+            ),
+            (
+                "pkg",
+                r#"
+                tasks.build.run = [{ task = "codegen" }, "cargo build"]
+                tasks.codegen.run = "echo $HOME"
+                "#,
+            ),
+        ]);
+
+        assert_eq!(
+            context.to_makefile().unwrap(),
+            "# Generated by `wrun --dump-makefile`
+
+.PHONY: lint
+lint:
+\tcargo clippy
+
+.PHONY: pkg/codegen
+pkg/codegen:
+\tcd pkg && echo $$HOME
+
+.PHONY: pkg/build
+pkg/build: pkg/codegen
+\tcd pkg && cargo build
+
+.PHONY: all
+all: lint pkg/build
+\t@echo done
+"
+        );
+    }
+
+    #[test]
+    fn cycles_are_broken() {
+        let context = Context::for_test(&[(
+            "",
+            r#"
+            tasks.a.run = { task = "b" }
+            tasks.b.run = [{ task = "a" }, "true"]
+            "#,
+        )]);
+
+        assert_eq!(
+            context.to_makefile().unwrap(),
+            "# Generated by `wrun --dump-makefile`
+
+.PHONY: b
+b:
+\ttrue
+
+.PHONY: a
+a: b
+"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn multi_line_commands() {
+        let project = TestProject::new(&[
+            (
+                PROJECT_FILE,
+                r#"
+                packages = ["pkg"]
+                tasks.lines.run = "echo a\necho b"
+                tasks.continued.run = "echo a \\\n  b"
+                "#,
+            ),
+            (
+                "pkg/wrun.toml",
+                r#"tasks.lines.run = "@pwd\ntrue\n\nx=$PWD && echo $x""#,
+            ),
+        ]);
+        let makefile = project.context().to_makefile().unwrap();
+        fs::write(project.0.join("Makefile"), makefile).unwrap();
+
+        let make = |target: &str| {
+            let output = Command::new("make")
+                .args(["-s", target])
+                .current_dir(&project.0)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{output:?}");
+            String::from_utf8(output.stdout).unwrap()
+        };
+        assert_eq!(make("lines"), "a\nb\n");
+        assert_eq!(make("continued"), "a b\n");
+
+        let pkg = fs::canonicalize(project.0.join("pkg")).unwrap();
+        let pkg = pkg.display();
+        assert_eq!(make("pkg/lines"), format!("{pkg}\n{pkg}\n"));
+    }
+
+    #[test]
+    fn quoted_directories_and_profiles() {
+        let mut context = Context::for_test(&[
+            ("", ""),
+            (
+                "it's",
+                r#"
+                tasks.build.run = "cargo build"
+                tasks.build.profiles.release = "cargo build --release"
+                "#,
+            ),
+        ]);
+        context.set_profile("release");
+
+        assert_eq!(
+            context.to_makefile().unwrap(),
+            "# Generated by `wrun --dump-makefile`
+
+.PHONY: it's/build
+it's/build:
+\tcd \"it's\" && cargo build --release
+"
+        );
+    }
+
+    #[test]
+    fn unsupported_options() {
+        let tasks = [
+            ("for-each", "run = 'ls'\nfor-each = '*'"),
+            ("matrix", "run = 'ls'\nmatrix.a.X = '1'"),
+            ("env", "run = 'ls'\nenv.X = '1'"),
+            ("shell", "run = 'ls'\nshell = false"),
+            ("capture", "run = { cmd = 'ls', capture = 'files' }"),
+            ("ok-codes", "run = { cmd = 'ls', ok-codes = [1] }"),
+            ("requires-env", "run = 'ls'\nrequires-env = ['X']"),
+            ("when.command", "run = 'ls'\nwhen.command = 'ls'"),
+            ("retry", "run = 'ls'\nretry.attempts = 2"),
+            ("pty", "run = 'ls'\npty = true"),
+            ("nice", "run = 'ls'\nnice = 10"),
+            ("repeatable", "run = 'ls'\nrepeatable = true"),
+            ("{{args}}", "run = 'ls {{args}}'"),
+            ("{{sha}}", "run = ['true', 'echo {{ sha }}']"),
+        ];
+        for (option, task) in tasks {
+            let context = Context::for_test(&[("", &format!("[tasks.a]\n{task}"))]);
+            assert_eq!(
+                context.to_makefile().unwrap_err().to_string(),
+                format!("cannot export /a to a Makefile, since it uses `{option}`")
+            );
+        }
+    }
+}
@@ -0,0 +1,287 @@
+//! Capturing command output rather than letting it go straight to wrun's
+//! stdout and stderr
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read as _, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::AtomicBool;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long to keep reading a killed command's output, in case something it
+/// started outside of its process group is still holding it open
+const KILLED_OUTPUT_GRACE: Duration = Duration::from_millis(100);
+
+/// Run `command`, copying only the first and last `max` lines of each of its
+/// stdout and stderr to `stdout` and `stderr`. Also returns up to the last
+/// `keep` lines of stderr, or `None` if it was killed for running past
+/// `deadline`.
+pub(crate) fn run_truncated(
+    mut command: Command,
+    max: usize,
+    keep: usize,
+    deadline: Option<Instant>,
+    mut stdout: impl Write + Send + 'static,
+    mut stderr: impl Write + Send + 'static,
+) -> io::Result<Option<(ExitStatus, Vec<String>)>> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let child_stdout = BufReader::new(child.stdout.take().unwrap());
+    let child_stderr = BufReader::new(child.stderr.take().unwrap());
+
+    let stderr = thread::spawn(move || copy_truncated(child_stderr, &mut stderr, max, keep));
+    let stdout = thread::spawn(move || copy_truncated(child_stdout, &mut stdout, max, 0));
+
+    let status = crate::wait(&mut child, deadline)?;
+    let killed = status.is_none();
+    finish(stdout, killed)?;
+    let stderr_tail = finish(stderr, killed)?.unwrap_or_default();
+    Ok(status.map(|status| (status, stderr_tail)))
+}
+
+/// Run `command` without showing anything it writes unless it fails or is
+/// killed for running past `deadline`, in which case all of its stdout and
+/// stderr are copied to `stdout` and `stderr`
+pub(crate) fn run_quiet(
+    mut command: Command,
+    deadline: Option<Instant>,
+    stdout: &mut impl Write,
+    stderr: &mut impl Write,
+) -> io::Result<Option<ExitStatus>> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut child_stdout = child.stdout.take().unwrap();
+    let mut child_stderr = child.stderr.take().unwrap();
+
+    let output = thread::spawn(move || {
+        let mut output = Vec::new();
+        child_stdout.read_to_end(&mut output).map(|_| output)
+    });
+    let errors = thread::spawn(move || {
+        let mut errors = Vec::new();
+        child_stderr.read_to_end(&mut errors).map(|_| errors)
+    });
+
+    let status = crate::wait(&mut child, deadline)?;
+    let killed = status.is_none();
+    let output = finish(output, killed)?.unwrap_or_default();
+    let errors = finish(errors, killed)?.unwrap_or_default();
+
+    if !status.is_some_and(|status| status.success()) {
+        stdout.write_all(&output)?;
+        stdout.flush()?;
+        stderr.write_all(&errors)?;
+        stderr.flush()?;
+    }
+    Ok(status)
+}
+
+/// Run `command`, returning its stdout with any trailing newlines removed
+/// instead of printing it, or `None` if it was killed for running past
+/// `deadline` or because `cancel` was set
+pub(crate) fn run_captured(
+    mut command: Command,
+    deadline: Option<Instant>,
+    cancel: Option<&AtomicBool>,
+) -> io::Result<Option<(ExitStatus, String)>> {
+    let mut child = command.stdout(Stdio::piped()).spawn()?;
+    let mut stdout = child.stdout.take().unwrap();
+
+    let output = thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+
+    // Nothing is returned from a killed command, so there is no need to wait
+    // for the rest of its output
+    let Some(status) = crate::wait_or_cancel(&mut child, deadline, cancel)? else {
+        return Ok(None);
+    };
+    let output = output.join().unwrap()?;
+    let output = String::from_utf8_lossy(&output);
+    Ok(Some((
+        status,
+        output.trim_end_matches(['\n', '\r']).to_owned(),
+    )))
+}
+
+/// Run `command`, writing each line of its stdout and stderr to wrun's after
+/// `prefix`, so it can be told apart from commands running alongside it.
+/// Returns `None` if it was killed for running past `deadline` or because
+/// `cancel` was set.
+pub(crate) fn run_prefixed(
+    mut command: Command,
+    prefix: &str,
+    deadline: Option<Instant>,
+    cancel: &AtomicBool,
+) -> io::Result<Option<ExitStatus>> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let stderr = BufReader::new(child.stderr.take().unwrap());
+
+    let prefix = prefix.to_owned();
+    let stdout = {
+        let prefix = prefix.clone();
+        thread::spawn(move || copy_prefixed(stdout, || io::stdout().lock(), &prefix))
+    };
+    let stderr = thread::spawn(move || copy_prefixed(stderr, || io::stderr().lock(), &prefix));
+
+    let status = crate::wait_or_cancel(&mut child, deadline, Some(cancel))?;
+    let killed = status.is_none();
+    finish(stdout, killed)?;
+    finish(stderr, killed)?;
+    Ok(status)
+}
+
+/// Wait for `reader` to finish copying a command's output. If the command was
+/// killed, give up after [`KILLED_OUTPUT_GRACE`] and leave it running instead.
+fn finish<T>(reader: JoinHandle<io::Result<T>>, killed: bool) -> io::Result<Option<T>> {
+    if killed {
+        let give_up = Instant::now() + KILLED_OUTPUT_GRACE;
+        while !reader.is_finished() {
+            if Instant::now() >= give_up {
+                return Ok(None);
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+    reader.join().unwrap().map(Some)
+}
+
+/// Copy `input` line by line after `prefix`, locking the output for each line
+/// so lines from other threads are not mixed into it
+fn copy_prefixed<W: Write>(
+    input: impl BufRead,
+    output: impl Fn() -> W,
+    prefix: &str,
+) -> io::Result<()> {
+    for line in input.split(b'\n') {
+        let line = line?;
+        let mut output = output();
+        output.write_all(prefix.as_bytes())?;
+        output.write_all(&line)?;
+        output.write_all(b"\n")?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+/// Copy `input` to `output` line by line, replacing everything but the first
+/// and last `max` lines with a marker. Returns the last `keep` lines, whether
+/// or not they were shown.
+pub(crate) fn copy_truncated(
+    input: impl BufRead,
+    output: &mut impl Write,
+    max: usize,
+    keep: usize,
+) -> io::Result<Vec<String>> {
+    let mut tail = VecDeque::new();
+    let mut skipped = 0_usize;
+    let mut recent = VecDeque::new();
+
+    for (i, line) in input.split(b'\n').enumerate() {
+        let line = line?;
+        if keep > 0 {
+            if recent.len() == keep {
+                recent.pop_front();
+            }
+            recent.push_back(String::from_utf8_lossy(&line).into_owned());
+        }
+
+        if i < max {
+            output.write_all(&line)?;
+            output.write_all(b"\n")?;
+            continue;
+        }
+
+        tail.push_back(line);
+        if tail.len() > max {
+            tail.pop_front();
+            skipped += 1;
+        }
+    }
+
+    if skipped > 0 {
+        writeln!(output, "… {skipped} lines truncated …")?;
+    }
+    for line in tail {
+        output.write_all(&line)?;
+        output.write_all(b"\n")?;
+    }
+    output.flush()?;
+
+    Ok(recent.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn truncate(command: &str, max: usize) -> String {
+        let mut child = Command::new("sh")
+            .args(["-c", command])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut output = Vec::new();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        copy_truncated(stdout, &mut output, max, 0).unwrap();
+        assert!(child.wait().unwrap().success());
+        String::from_utf8(output).unwrap()
+    }
+
+    const COUNT_TO_10: &str = "i=1; while [ $i -le 10 ]; do echo $i; i=$((i + 1)); done";
+
+    #[test]
+    fn truncates_long_output() {
+        assert_eq!(
+            truncate(COUNT_TO_10, 2),
+            "1\n2\n… 6 lines truncated …\n9\n10\n"
+        );
+        assert_eq!(truncate(COUNT_TO_10, 0), "… 10 lines truncated …\n");
+    }
+
+    #[test]
+    fn keeps_short_output() {
+        assert_eq!(truncate(COUNT_TO_10, 5), "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n");
+        assert_eq!(truncate("printf 'no newline'", 1), "no newline\n");
+    }
+
+    #[test]
+    fn quiet_unless_failed() {
+        let quiet = |script: &str| {
+            let mut command = Command::new("sh");
+            command.args(["-c", script]);
+            let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+            let status = run_quiet(command, None, &mut stdout, &mut stderr).unwrap();
+            let output = |bytes| String::from_utf8(bytes).unwrap();
+            (status.unwrap().success(), output(stdout), output(stderr))
+        };
+
+        let passed = quiet("echo out; echo err >&2");
+        assert_eq!(passed, (true, String::new(), String::new()));
+        let failed = quiet("echo out; echo err >&2; exit 1");
+        assert_eq!(failed, (false, "out\n".to_owned(), "err\n".to_owned()));
+    }
+
+    #[test]
+    fn keeps_recent_lines() {
+        let child = Command::new("sh")
+            .args(["-c", COUNT_TO_10])
+            .stdout(Stdio::piped())
+            .spawn();
+        let mut child = child.unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let recent = copy_truncated(stdout, &mut io::sink(), 1, 3).unwrap();
+        assert!(child.wait().unwrap().success());
+        assert_eq!(recent, ["8", "9", "10"]);
+    }
+}
@@ -0,0 +1,494 @@
+//! Running requested tasks alongside each other, with
+//! [`Context::set_jobs`](crate::Context::set_jobs)
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread::{self, Scope};
+use std::time::{Duration, Instant};
+
+use crate::summary::{SkipReason, Summary, TaskStatus};
+use crate::{AbsoluteTaskName, Plan, PlanEntry, exit_code, output, template};
+
+impl Plan<'_> {
+    /// Like [`execute`](Self::execute), but running up to the context's job
+    /// count of groups at once. The first failure stops every other running
+    /// command.
+    pub(crate) fn execute_parallel(self, prerun: impl Fn(&PlanEntry)) -> anyhow::Result<Summary> {
+        let cancel = AtomicBool::new(false);
+        let mut scheduler = Scheduler::new(&self, &cancel, &prerun);
+        let (sender, receiver) = mpsc::channel();
+
+        let interrupts = Interrupts::catch();
+        let result = thread::scope(|scope| {
+            let result = scheduler.run(scope, &sender, &receiver);
+            if result.is_err() {
+                cancel.store(true, Ordering::Relaxed);
+            }
+            result
+        });
+        interrupts.restore();
+        result?;
+
+        let mut summary = self.skipped_summary();
+        for group in scheduler.groups {
+            for (task, status) in group.statuses {
+                summary.record(&task, status);
+            }
+        }
+        Ok(summary)
+    }
+}
+
+/// Since each command runs in its own process group, the terminal's Ctrl-C
+/// only reaches wrun. Commands are stopped before wrun is interrupted too.
+#[cfg(unix)]
+struct Interrupts {
+    previous: Vec<(libc::c_int, libc::sighandler_t)>,
+}
+
+#[cfg(unix)]
+static INTERRUPTED: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(0);
+
+#[cfg(unix)]
+impl Interrupts {
+    const SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+    fn catch() -> Self {
+        extern "C" fn handle(signal: libc::c_int) {
+            INTERRUPTED.store(signal, Ordering::Relaxed);
+        }
+
+        INTERRUPTED.store(0, Ordering::Relaxed);
+        let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        let mut previous = Vec::new();
+        for signal in Self::SIGNALS {
+            // SAFETY: the handler only stores to an atomic
+            let old = unsafe { libc::signal(signal, handler) };
+            if old == libc::SIG_IGN {
+                // Leave signals ignored, eg for background jobs
+                unsafe { libc::signal(signal, old) };
+            } else {
+                previous.push((signal, old));
+            }
+        }
+        Self { previous }
+    }
+
+    fn received() -> bool {
+        INTERRUPTED.load(Ordering::Relaxed) != 0
+    }
+
+    /// Put back the previous handlers, then pass on any signal that arrived
+    fn restore(self) {
+        for (signal, previous) in self.previous {
+            // SAFETY: restores what was there before
+            unsafe { libc::signal(signal, previous) };
+        }
+        let signal = INTERRUPTED.swap(0, Ordering::Relaxed);
+        if signal != 0 {
+            // SAFETY: just sends a signal
+            unsafe { libc::raise(signal) };
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct Interrupts;
+
+#[cfg(not(unix))]
+impl Interrupts {
+    fn catch() -> Self {
+        Self
+    }
+
+    fn received() -> bool {
+        false
+    }
+
+    fn restore(self) {}
+}
+
+/// The commands of one requested task, which run in order
+struct Group<'p> {
+    name: &'p AbsoluteTaskName,
+    entries: VecDeque<&'p PlanEntry>,
+    state: State,
+    /// Recorded into the summary in plan order once everything is done, so
+    /// it does not depend on which group finished first
+    statuses: Vec<(AbsoluteTaskName, TaskStatus)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Waiting,
+    Running,
+    Done,
+}
+
+/// Sent back by the thread running a command
+struct Finished {
+    group: usize,
+    entry: PlanEntry,
+    attempt: u32,
+    /// `None` if the command was killed, along with its stdout if it was
+    /// captured
+    result: io::Result<Option<(ExitStatus, Option<String>)>>,
+}
+
+struct Scheduler<'p, F> {
+    plan: &'p Plan<'p>,
+    prerun: &'p F,
+    cancel: &'p AtomicBool,
+    groups: Vec<Group<'p>>,
+    deadline: Option<Instant>,
+    /// Commands currently running
+    in_flight: usize,
+    /// Commands started so far, for `stop_after`
+    started: usize,
+    failed: bool,
+    timed_out: bool,
+    captured: HashMap<AbsoluteTaskName, HashMap<String, String>>,
+}
+
+impl<'p, F: Fn(&PlanEntry)> Scheduler<'p, F> {
+    fn new(plan: &'p Plan<'p>, cancel: &'p AtomicBool, prerun: &'p F) -> Self {
+        let mut groups = Vec::<Group>::new();
+        for entry in &plan.plan {
+            match groups.iter_mut().find(|group| *group.name == entry.group) {
+                Some(group) => group.entries.push_back(entry),
+                None => groups.push(Group {
+                    name: &entry.group,
+                    entries: VecDeque::from([entry]),
+                    state: State::Waiting,
+                    statuses: Vec::new(),
+                }),
+            }
+        }
+
+        Self {
+            plan,
+            prerun,
+            cancel,
+            groups,
+            deadline: plan.context.max_time.map(|max| Instant::now() + max),
+            in_flight: 0,
+            started: 0,
+            failed: false,
+            timed_out: false,
+            captured: HashMap::new(),
+        }
+    }
+
+    fn run<'s>(
+        &mut self,
+        scope: &'s Scope<'s, '_>,
+        sender: &mpsc::Sender<Finished>,
+        receiver: &mpsc::Receiver<Finished>,
+    ) -> anyhow::Result<()>
+    where
+        'p: 's,
+    {
+        loop {
+            self.start_ready(scope, sender)?;
+            if self.in_flight == 0 {
+                return Ok(());
+            }
+
+            let finished = match receiver.recv_timeout(Duration::from_millis(10)) {
+                Ok(finished) => finished,
+                Err(_) => {
+                    if Interrupts::received() {
+                        self.cancel.store(true, Ordering::Relaxed);
+                    }
+                    continue;
+                }
+            };
+            self.in_flight -= 1;
+            self.finish(finished, scope, sender)?;
+        }
+    }
+
+    /// Start waiting groups while there are free jobs and the groups they
+    /// wait for are done
+    fn start_ready<'s>(
+        &mut self,
+        scope: &'s Scope<'s, '_>,
+        sender: &mpsc::Sender<Finished>,
+    ) -> anyhow::Result<()>
+    where
+        'p: 's,
+    {
+        loop {
+            let running = self
+                .groups
+                .iter()
+                .filter(|group| group.state == State::Running);
+            if running.count() >= self.plan.context.jobs {
+                return Ok(());
+            }
+
+            let ready = self
+                .groups
+                .iter()
+                .position(|group| group.state == State::Waiting && !self.is_blocked(group.name));
+            let Some(index) = ready else {
+                return Ok(());
+            };
+            self.groups[index].state = State::Running;
+            self.advance(index, scope, sender)?;
+        }
+    }
+
+    /// Whether `group` waits for another group that is not done
+    fn is_blocked(&self, group: &AbsoluteTaskName) -> bool {
+        self.plan
+            .waits
+            .iter()
+            .filter(|(waiting, _)| waiting == group)
+            .any(|(_, other)| {
+                self.groups
+                    .iter()
+                    .any(|group| group.name == other && group.state != State::Done)
+            })
+    }
+
+    /// Start the next command of the group at `index`, skipping those that
+    /// should not run, or mark it done if there are none left
+    fn advance<'s>(
+        &mut self,
+        index: usize,
+        scope: &'s Scope<'s, '_>,
+        sender: &mpsc::Sender<Finished>,
+    ) -> anyhow::Result<()>
+    where
+        'p: 's,
+    {
+        while let Some(entry) = self.groups[index].entries.pop_front() {
+            let status = if self.timed_out {
+                Some(TaskStatus::Skipped(SkipReason::TimeLimit))
+            } else if self.failed {
+                Some(TaskStatus::Skipped(SkipReason::EarlierFailure))
+            } else if self
+                .plan
+                .context
+                .stop_after
+                .is_some_and(|max| self.started >= max)
+            {
+                Some(TaskStatus::Skipped(SkipReason::StoppedEarly))
+            } else if self.is_past_deadline() {
+                self.timed_out = true;
+                Some(TaskStatus::TimedOut)
+            } else {
+                None
+            };
+
+            if let Some(status) = status {
+                self.groups[index]
+                    .statuses
+                    .push((entry.task.clone(), status));
+                continue;
+            }
+
+            let entry = match self.captured.get(&entry.task) {
+                Some(values) => {
+                    let lookup = |name: &str| values.get(name).map(String::as_str);
+                    PlanEntry {
+                        command: template::render(&entry.command, lookup).0,
+                        ..entry.clone()
+                    }
+                }
+                None => entry.clone(),
+            };
+            self.started += 1;
+            return self.spawn(index, entry, 1, scope, sender);
+        }
+
+        self.groups[index].state = State::Done;
+        Ok(())
+    }
+
+    fn spawn<'s>(
+        &mut self,
+        group: usize,
+        entry: PlanEntry,
+        attempt: u32,
+        scope: &'s Scope<'s, '_>,
+        sender: &mpsc::Sender<Finished>,
+    ) -> anyhow::Result<()>
+    where
+        'p: 's,
+    {
+        (self.prerun)(&entry);
+
+        let mut command = self.plan.command(&entry)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt as _;
+
+            // So cancelling also stops anything the command started
+            command.process_group(0);
+        }
+        let delay = entry
+            .retry
+            .filter(|_| attempt > 1)
+            .map(|retry| retry.jittered_delay(attempt - 1));
+        let prefix = format!("[{}] ", entry.task.display(self.plan.context.separator));
+        let (deadline, cancel, sender) = (self.deadline, self.cancel, sender.clone());
+
+        self.in_flight += 1;
+        scope.spawn(move || {
+            if let Some(delay) = delay {
+                crate::sleep_until(delay, deadline);
+            }
+
+            let result = if entry.capture.is_some() {
+                output::run_captured(command, deadline, Some(cancel))
+                    .map(|result| result.map(|(exit, stdout)| (exit, Some(stdout))))
+            } else {
+                output::run_prefixed(command, &prefix, deadline, cancel)
+                    .map(|result| result.map(|exit| (exit, None)))
+            };
+            let _ = sender.send(Finished {
+                group,
+                entry,
+                attempt,
+                result,
+            });
+        });
+        Ok(())
+    }
+
+    fn finish<'s>(
+        &mut self,
+        finished: Finished,
+        scope: &'s Scope<'s, '_>,
+        sender: &mpsc::Sender<Finished>,
+    ) -> anyhow::Result<()>
+    where
+        'p: 's,
+    {
+        let Finished {
+            group,
+            entry,
+            attempt,
+            result,
+        } = finished;
+
+        let status = match result? {
+            None if self.is_past_deadline() => {
+                self.timed_out = true;
+                TaskStatus::TimedOut
+            }
+            None => TaskStatus::Cancelled,
+            Some((exit, stdout)) if entry.succeeded(exit) => {
+                if let (Some(name), Some(stdout)) = (&entry.capture, stdout) {
+                    let values = self.captured.entry(entry.task.clone()).or_default();
+                    values.insert(name.clone(), stdout);
+                }
+                TaskStatus::Ran
+            }
+            Some(_)
+                if !self.failed && attempt < entry.retry.map_or(1, |retry| retry.attempts()) =>
+            {
+                return self.spawn(group, entry, attempt + 1, scope, sender);
+            }
+            Some((exit, _)) => {
+                self.failed = true;
+                self.cancel.store(true, Ordering::Relaxed);
+                TaskStatus::Failed {
+                    code: exit_code(exit),
+                }
+            }
+        };
+
+        self.groups[group].statuses.push((entry.task, status));
+        self.advance(group, scope, sender)
+    }
+
+    fn is_past_deadline(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TestProject;
+    use crate::{PROJECT_FILE, TaskName};
+
+    fn execute(project: &TestProject, tasks: &[&str]) -> Summary {
+        let mut context = project.context();
+        context.set_jobs(2);
+        let mut plan = context.plan();
+        for task in tasks {
+            plan.push(&TaskName::new(task).relative_to("").unwrap())
+                .unwrap();
+        }
+        plan.execute(|_| {}).unwrap()
+    }
+
+    #[test]
+    fn runs_alongside() {
+        // Each task waits for the other to start, failing after a few seconds
+        let wait = |file| {
+            format!("for i in $(seq 100); do [ -e {file} ] && exit; sleep 0.05; done; exit 1")
+        };
+        let project = TestProject::new(&[(
+            PROJECT_FILE,
+            &format!(
+                "tasks.a.run = ['touch a', '{}']\ntasks.b.run = ['touch b', '{}']",
+                wait("b"),
+                wait("a"),
+            ),
+        )]);
+
+        let summary = execute(&project, &["/a", "/b"]);
+        assert_eq!(summary.exit_code(), None);
+    }
+
+    #[test]
+    fn failure_stops_others() {
+        let project = TestProject::new(&[(
+            PROJECT_FILE,
+            r#"
+            tasks.fail.run = ["sleep 0.1", "exit 3"]
+            tasks.slow.run = ["sleep 10", "true"]
+            tasks.after.run = "true"
+            "#,
+        )]);
+
+        let start = Instant::now();
+        let summary = execute(&project, &["/slow", "/fail", "/after"]);
+        assert!(start.elapsed().as_secs() < 5);
+        assert_eq!(summary.exit_code(), Some(3));
+
+        let statuses = summary
+            .tasks()
+            .map(|(_, status)| status)
+            .collect::<Vec<_>>();
+        assert_eq!(statuses, [
+            &TaskStatus::Cancelled,
+            &TaskStatus::Failed { code: 3 },
+            &TaskStatus::Skipped(SkipReason::EarlierFailure),
+        ]);
+    }
+
+    #[test]
+    fn waits_for_shared_tasks() {
+        let project = TestProject::new(&[(
+            PROJECT_FILE,
+            r#"
+            tasks.codegen.run = "sleep 0.2 && touch generated"
+            tasks.lint.run = [{ task = "codegen" }, "test -e generated"]
+            tasks.test.run = [{ task = "codegen" }, "test -e generated"]
+            "#,
+        )]);
+
+        let summary = execute(&project, &["/lint", "/test"]);
+        assert_eq!(summary.exit_code(), None);
+    }
+}
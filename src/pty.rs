@@ -0,0 +1,102 @@
+//! Running commands attached to a pseudo-terminal, for programs that behave
+//! differently when their output is not a TTY
+
+use std::fs::File;
+use std::io::{self, Read as _, Write};
+use std::os::fd::{FromRawFd as _, OwnedFd};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::Instant;
+use std::{mem, ptr, thread};
+
+/// Run `command` with a new pseudo-terminal as its stdin, stdout, and stderr,
+/// copying everything it writes to `output`. Input is not forwarded. Returns
+/// `None` if it was killed for running past `deadline`.
+pub(crate) fn run(
+    mut command: Command,
+    output: &mut (impl Write + Send),
+    deadline: Option<Instant>,
+) -> io::Result<Option<ExitStatus>> {
+    let (master, slave) = open()?;
+
+    command
+        .stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave));
+    let mut child = command.spawn()?;
+    // The command holds the last copies of the slave side, which need to be
+    // closed to see the end of the output
+    drop(command);
+
+    let mut master = File::from(master);
+    thread::scope(|scope| {
+        let copy = scope.spawn(move || {
+            let mut buffer = [0; 4096];
+            loop {
+                match master.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(read) => output.write_all(&buffer[..read])?,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    // Linux reports EIO once the slave side has been closed
+                    Err(err) if err.raw_os_error() == Some(libc::EIO) => break,
+                    Err(err) => return Err(err),
+                }
+            }
+            output.flush()
+        });
+
+        let status = crate::wait(&mut child, deadline)?;
+        copy.join().unwrap()?;
+        Ok(status)
+    })
+}
+
+fn open() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut master = -1;
+    let mut slave = -1;
+
+    // Match wrun's own terminal size, if it has one
+    // SAFETY: winsize is plain old data and TIOCGWINSZ only writes to it
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    let has_size = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    let size = if has_size {
+        &mut size as *mut _
+    } else {
+        ptr::null_mut()
+    };
+
+    // SAFETY: openpty only writes the two file descriptors, and the name and
+    // termios pointers may be null
+    let result = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            size,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: openpty succeeded, so both are open file descriptors owned by
+    // nothing else
+    unsafe { Ok((OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave))) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_tty() {
+        let script = "if [ -t 0 ] && [ -t 1 ]; then echo tty; else echo not; fi";
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+
+        let mut output = Vec::new();
+        let status = run(command, &mut output, None).unwrap().unwrap();
+        assert!(status.success());
+        assert_eq!(String::from_utf8(output).unwrap().trim_end(), "tty");
+    }
+}
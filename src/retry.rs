@@ -0,0 +1,113 @@
+use std::hash::{BuildHasher as _, RandomState};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+/// How many times to try something that failed, and how long to wait between
+/// attempts
+#[serde_as]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first
+    attempts: u32,
+    /// Delay before the first retry, doubling for each subsequent one
+    #[serde(default = "default_base_delay")]
+    #[serde_as(as = "serde_with::DurationSecondsWithFrac<f64>")]
+    base_delay: Duration,
+    #[serde(default = "default_max_delay")]
+    #[serde_as(as = "serde_with::DurationSecondsWithFrac<f64>")]
+    max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(attempts: u32, base_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            attempts,
+            base_delay,
+            max_delay,
+        }
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts.max(1)
+    }
+
+    /// Delay before the `retry`th retry (starting at 1), without any jitter
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(retry.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// [`delay`](Self::delay) with jitter applied, somewhere between half and
+    /// all of the full delay
+    pub fn jittered_delay(&self, retry: u32) -> Duration {
+        let random = RandomState::new().hash_one(retry);
+        self.jitter(retry, random as f64 / u64::MAX as f64)
+    }
+
+    fn jitter(&self, retry: u32, random: f64) -> Duration {
+        let delay = self.delay(retry);
+        delay / 2 + (delay / 2).mul_f64(random)
+    }
+}
+
+fn default_base_delay() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_max_delay() -> Duration {
+    Duration::from_secs(30)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(base: u64, max: u64) -> RetryPolicy {
+        RetryPolicy::new(5, Duration::from_secs(base), Duration::from_secs(max))
+    }
+
+    #[test]
+    fn exponential_schedule() {
+        let policy = policy(1, 60);
+        let delays = (1..=4)
+            .map(|n| policy.delay(n).as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [1, 2, 4, 8]);
+    }
+
+    #[test]
+    fn clamped_to_max_delay() {
+        let policy = policy(3, 10);
+        assert_eq!(policy.delay(2), Duration::from_secs(6));
+        assert_eq!(policy.delay(3), Duration::from_secs(10));
+        assert_eq!(policy.delay(40), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn jitter_bounds() {
+        let policy = policy(4, 60);
+        assert_eq!(policy.jitter(1, 0.0), Duration::from_secs(2));
+        assert_eq!(policy.jitter(1, 1.0), Duration::from_secs(4));
+
+        for retry in 1..10 {
+            let delay = policy.jittered_delay(retry);
+            assert!(delay >= policy.delay(retry) / 2);
+            assert!(delay <= policy.delay(retry));
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let policy: RetryPolicy = toml::from_str("attempts = 3").unwrap();
+        assert_eq!(
+            policy,
+            RetryPolicy::new(3, default_base_delay(), default_max_delay())
+        );
+
+        let policy: RetryPolicy = toml::from_str("attempts = 2\nbase-delay = 0.5").unwrap();
+        assert_eq!(policy.delay(1), Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,238 @@
+//! One-way export of a plan as a shell script
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use anyhow::bail;
+
+use crate::{Plan, PlanEntry, Shell};
+
+impl Plan<'_> {
+    /// Export the planned commands as a POSIX shell script that stops at the
+    /// first failure. Each command runs in the same directory as it would
+    /// have, relative to `ROOT`, and the variables wrun would set are
+    /// exported. Commands that are not silent are traced with `set -x`.
+    ///
+    /// Retries and time limits are left out, and commands with `capture`
+    /// cannot be exported.
+    pub fn to_script(&self) -> anyhow::Result<String> {
+        let env = self.env()?;
+        let mut out = String::from("#!/bin/sh\n# Generated by `wrun --to-script`\nset -e\n\n");
+        for (key, value) in &env {
+            writeln!(out, "export {key}={}", quote(&value.to_string_lossy())?)?;
+        }
+
+        let mut task = None;
+        let mut directory = None;
+        let mut tracing = false;
+        for entry in &self.plan {
+            if let Some(name) = &entry.capture {
+                bail!(
+                    "{} captures the output of `{}` as {{{{{name}}}}}, which scripts do not \
+                     support",
+                    entry.task.display(self.context.separator),
+                    entry.command,
+                );
+            }
+
+            if task != Some(&entry.task) {
+                writeln!(out, "\n# {}", entry.task.display(self.context.separator))?;
+                task = Some(&entry.task);
+            }
+            if directory != Some(&entry.directory) {
+                writeln!(out, "cd {}", self.script_directory(&entry.directory)?)?;
+                directory = Some(&entry.directory);
+            }
+            if tracing == entry.silent {
+                tracing = !entry.silent;
+                out.push_str(if tracing { "set -x\n" } else { "set +x\n" });
+            }
+
+            // Variables only set for this entry, eg from its task's `env`
+            let extra = self
+                .entry_env(entry)?
+                .into_iter()
+                .filter(|(key, value)| env.get(key) != Some(value))
+                .collect::<Vec<_>>();
+            let command = self.script_command(entry)?;
+            if extra.is_empty() {
+                writeln!(out, "{command}")?;
+            } else {
+                out.push_str("(\n");
+                for (key, value) in extra {
+                    writeln!(out, "export {key}={}", quote(&value.to_string_lossy())?)?;
+                }
+                writeln!(out, "{command}\n)")?;
+            }
+        }
+
+        Ok(out)
+    }
+
+    fn script_directory(&self, directory: &Path) -> anyhow::Result<String> {
+        match directory.strip_prefix(&self.context.root) {
+            Ok(relative) if relative.as_os_str().is_empty() => Ok(r#""$ROOT""#.to_owned()),
+            Ok(relative) => Ok(format!(
+                r#""$ROOT"/{}"#,
+                quote(&relative.to_string_lossy())?
+            )),
+            Err(_) => quote(&directory.to_string_lossy()),
+        }
+    }
+
+    /// The line(s) of script that run `entry`'s command like its shell would
+    fn script_command(&self, entry: &PlanEntry) -> anyhow::Result<String> {
+        // cmd parses its own command line, so there is no way to quote a
+        // command for it from sh
+        if let Some(program) = entry
+            .shell
+            .program()
+            .filter(|program| crate::is_cmd(program))
+        {
+            bail!(
+                "{} runs `{}` with {program}, which scripts cannot reproduce",
+                entry.task.display(self.context.separator),
+                entry.command,
+            );
+        }
+
+        let command = match &entry.shell {
+            Shell::Default => entry.command.clone(),
+            Shell::Program(shell) => {
+                let command = shell.iter().map(String::as_str).chain([entry.command()]);
+                shlex::try_join(command)?
+            }
+            Shell::Direct => {
+                let Some(words) = shlex::split(&entry.command) else {
+                    bail!("cannot split `{}` into words", entry.command);
+                };
+                shlex::try_join(words.iter().map(String::as_str))?
+            }
+        };
+
+        if entry.ok_codes.is_empty() {
+            return Ok(command);
+        }
+        let codes = entry
+            .ok_codes
+            .iter()
+            .map(i32::to_string)
+            .collect::<Vec<_>>()
+            .join("|");
+        Ok(format!(
+            "{{\n{command}\n}} || {{\n  status=$?\n  case $status in {codes}) ;; *) exit $status \
+             ;; esac\n}}"
+        ))
+    }
+}
+
+fn quote(value: &str) -> anyhow::Result<String> {
+    Ok(shlex::try_quote(value)?.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{Context, TaskName};
+
+    fn script(context: &mut Context, task: &str) -> anyhow::Result<String> {
+        let mut plan = context.plan();
+        plan.push(&TaskName::new(task).relative_to("").unwrap())?;
+        plan.to_script()
+    }
+
+    // Windows' default shell cannot be exported
+    #[cfg(unix)]
+    #[test]
+    fn commands_directories_and_env() {
+        let mut context = Context::for_test(&[
+            (
+                "",
+                r#"
+                tasks.test.run = [{ task = "pkg/build" }, "cargo test", "@echo 'done'"]
+                "#,
+            ),
+            (
+                "pkg",
+                r#"
+                tasks.build.run = ["@mkdir -p out", { cmd = "grep -q x log", ok-codes = [1] }]
+                tasks.build.env = { PROFILE = "release" }
+                "#,
+            ),
+        ]);
+        context.root = PathBuf::from("/project");
+        context.set_env("CI", "it's true");
+
+        let script = script(&mut context, "/test").unwrap();
+        assert!(script.starts_with("#!/bin/sh\n# Generated by `wrun --to-script`\nset -e\n"));
+        assert!(script.contains("\nexport CI=\"it's true\"\n"));
+        assert!(script.contains("\nexport ROOT=/project\n"));
+
+        let (_, commands) = script.split_once("\n\n# ").unwrap();
+        assert_eq!(
+            commands,
+            r#"pkg/build
+cd "$ROOT"/pkg
+(
+export PROFILE=release
+mkdir -p out
+)
+set -x
+(
+export PROFILE=release
+{
+grep -q x log
+} || {
+  status=$?
+  case $status in 1) ;; *) exit $status ;; esac
+}
+)
+
+# /test
+cd "$ROOT"
+cargo test
+set +x
+echo 'done'
+"#
+        );
+    }
+
+    #[test]
+    fn shells_and_captures() {
+        let mut context = Context::for_test(&[(
+            "",
+            r#"
+            tasks.bash = { run = "echo $BASH_VERSION", shell = ["bash", "-c"] }
+            tasks.direct = { run = "ls 'my dir'", shell = false }
+            tasks.sh = { run = "echo $0", shell = ["sh", "-c"] }
+            tasks.cmd = { run = "echo %PATH%", shell = ["cmd.exe", "/C"] }
+            tasks.captured.run = [{ cmd = "git rev-parse HEAD", capture = "sha" }, "echo {{sha}}"]
+            "#,
+        )]);
+
+        let commands = |script: String| script.lines().last().unwrap().to_owned();
+        assert_eq!(
+            commands(script(&mut context, "/bash").unwrap()),
+            "bash -c 'echo $BASH_VERSION'"
+        );
+        assert_eq!(
+            commands(script(&mut context, "/direct").unwrap()),
+            "ls 'my dir'"
+        );
+        // Only the default shell is left to the script's own
+        assert_eq!(
+            commands(script(&mut context, "/sh").unwrap()),
+            "sh -c 'echo $0'"
+        );
+        assert_eq!(
+            script(&mut context, "/cmd").unwrap_err().to_string(),
+            "/cmd runs `echo %PATH%` with cmd.exe, which scripts cannot reproduce"
+        );
+        assert_eq!(
+            script(&mut context, "/captured").unwrap_err().to_string(),
+            "/captured captures the output of `git rev-parse HEAD` as {{sha}}, which scripts do \
+             not support"
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use std::fmt;
+
+use crate::AbsoluteTaskName;
+
+/// What happened to each task in an executed [`Plan`](crate::Plan)
+#[derive(Debug, Default)]
+pub struct Summary {
+    tasks: Vec<(AbsoluteTaskName, TaskStatus)>,
+    stderr_tail: Option<(AbsoluteTaskName, Vec<String>)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskStatus {
+    Ran,
+    Skipped(SkipReason),
+    Failed {
+        code: i32,
+    },
+    /// Was running, or about to be, when the time limit ran out
+    TimedOut,
+    /// Was stopped while running because a task running alongside it failed
+    Cancelled,
+    /// Ran some of its commands before reaching the limit from `--stop-after`
+    Partial,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    EarlierFailure,
+    TimeLimit,
+    /// Not reached before the number of commands from `--stop-after`
+    StoppedEarly,
+    /// Its `when.command` is not on `PATH`
+    MissingCommand(String),
+}
+
+impl Summary {
+    /// Record the outcome of one of `task`'s commands. A task that ran some
+    /// commands keeps its first status unless a later one fails, or it is
+    /// stopped partway through by `--stop-after`.
+    pub(crate) fn record(&mut self, task: &AbsoluteTaskName, status: TaskStatus) {
+        let existing = self.tasks.iter_mut().find(|(name, _)| name == task);
+        match (existing, status) {
+            (
+                Some((_, existing)),
+                status @ (TaskStatus::Failed { .. } | TaskStatus::TimedOut | TaskStatus::Cancelled),
+            ) => *existing = status,
+            (
+                Some((_, existing @ TaskStatus::Ran)),
+                TaskStatus::Skipped(SkipReason::StoppedEarly),
+            ) => *existing = TaskStatus::Partial,
+            (Some(_), _) => {}
+            (None, status) => self.tasks.push((task.clone(), status)),
+        }
+    }
+
+    pub(crate) fn record_stderr_tail(&mut self, task: &AbsoluteTaskName, tail: Vec<String>) {
+        if self.stderr_tail.is_none() && !tail.is_empty() {
+            self.stderr_tail = Some((task.clone(), tail));
+        }
+    }
+
+    /// The last lines the first failed command wrote to stderr, if its output
+    /// was captured
+    pub fn stderr_tail(&self) -> Option<(&AbsoluteTaskName, &[String])> {
+        self.stderr_tail
+            .as_ref()
+            .map(|(task, tail)| (task, tail.as_slice()))
+    }
+
+    pub fn tasks(&self) -> impl Iterator<Item = (&AbsoluteTaskName, &TaskStatus)> {
+        self.tasks.iter().map(|(name, status)| (name, status))
+    }
+
+    /// Exit code of the first failed task, if any. Running out of time exits
+    /// with 124, like `timeout(1)`.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.tasks().find_map(|(_, status)| match status {
+            TaskStatus::Failed { code } => Some(*code),
+            TaskStatus::TimedOut => Some(124),
+            _ => None,
+        })
+    }
+
+    /// Whether the summary is worth showing, ie more than one task was
+    /// involved or anything did not run successfully
+    pub fn is_interesting(&self) -> bool {
+        self.tasks.len() > 1 || self.tasks().any(|(_, status)| *status != TaskStatus::Ran)
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EarlierFailure => f.write_str("an earlier command failed"),
+            Self::TimeLimit => f.write_str("ran out of time"),
+            Self::StoppedEarly => f.write_str("stopped early"),
+            Self::MissingCommand(command) => write!(f, "`{command}` is not on PATH"),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! `{{name}}` placeholders in strings from package files
+
+/// Replace each `{{name}}` in `template` with `lookup(name)`. Placeholders
+/// `lookup` does not know are left as is and returned alongside the result.
+pub(crate) fn render<'a>(
+    template: &str,
+    lookup: impl Fn(&str) -> Option<&'a str>,
+) -> (String, Vec<String>) {
+    let mut rendered = String::with_capacity(template.len());
+    let mut unknown = Vec::new();
+
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        let end = start + end;
+
+        rendered.push_str(&rest[..start]);
+        let name = rest[start + 2..end].trim();
+        match lookup(name) {
+            Some(value) => rendered.push_str(value),
+            None => {
+                rendered.push_str(&rest[start..end + 2]);
+                unknown.push(name.to_owned());
+            }
+        }
+        rest = &rest[end + 2..];
+    }
+    rendered.push_str(rest);
+
+    (rendered, unknown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<&'static str> {
+        match name {
+            "package" => Some("foo"),
+            "empty" => Some(""),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(
+            render("Build {{package}}", lookup),
+            ("Build foo".to_owned(), vec![])
+        );
+        assert_eq!(render("{{ package }}{{empty}}!", lookup).0, "foo!");
+        assert_eq!(render("no placeholders", lookup).0, "no placeholders");
+        assert_eq!(render("unclosed {{package", lookup).0, "unclosed {{package");
+    }
+
+    #[test]
+    fn unknown_placeholders() {
+        assert_eq!(
+            render("{{nope}} {{package}}", lookup),
+            ("{{nope}} foo".to_owned(), vec!["nope".to_owned()])
+        );
+    }
+}
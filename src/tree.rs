@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::path::Path;
+use std::rc::Rc;
+
+use anyhow::bail;
+
+use crate::data::{Package, Run, Task};
+use crate::{AbsoluteTaskName, Context};
+
+/// A task along with all of the tasks it runs
+#[derive(Debug)]
+pub struct TaskTree {
+    name: AbsoluteTaskName,
+    children: Vec<TaskTree>,
+    cycle: bool,
+}
+
+impl TaskTree {
+    pub fn name(&self) -> &AbsoluteTaskName {
+        &self.name
+    }
+
+    pub fn children(&self) -> &[TaskTree] {
+        &self.children
+    }
+
+    /// Whether this task is already being expanded further up the tree. If so,
+    /// it will not have any children.
+    pub fn is_cycle(&self) -> bool {
+        self.cycle
+    }
+}
+
+impl Context {
+    pub fn tree(&self, name: &AbsoluteTaskName) -> anyhow::Result<TaskTree> {
+        self.build_tree(name, &mut Vec::new(), &mut HashMap::new())
+    }
+
+    fn build_tree(
+        &self,
+        name: &AbsoluteTaskName,
+        stack: &mut Vec<AbsoluteTaskName>,
+        unlisted: &mut HashMap<String, Package>,
+    ) -> anyhow::Result<TaskTree> {
+        if stack.contains(name) {
+            return Ok(TaskTree {
+                name: name.clone(),
+                children: Vec::new(),
+                cycle: true,
+            });
+        }
+
+        let task = self.find_task(name, unlisted)?;
+
+        stack.push(name.clone());
+        let mut children = Vec::new();
+        for run in task.run_for(self.profile.as_deref()) {
+            if let Run::Task(child) = run {
+                let child = child.clone().relative_to(name.package())?;
+                children.push(self.build_tree(&child, stack, unlisted)?);
+            }
+        }
+        stack.pop();
+
+        Ok(TaskTree {
+            name: name.clone(),
+            children,
+            cycle: false,
+        })
+    }
+
+    /// Like [`Plan::push`](crate::Plan::push), but without caching packages
+    /// missing from the project's package list
+    pub(crate) fn find_task(
+        &self,
+        name: &AbsoluteTaskName,
+        unlisted: &mut HashMap<String, Package>,
+    ) -> anyhow::Result<Rc<Task>> {
+        let package_name = name.package();
+        let package = match self.packages.get(package_name) {
+            Some(package) => package,
+            None => match unlisted.entry(package_name.to_owned()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(self.load_package(Path::new(package_name))?),
+            },
+        };
+
+        let Some(task) = package.tasks.0.get(name.task()) else {
+            bail!("Cannot find task: {}", name.display(self.separator))
+        };
+        Ok(Rc::clone(task))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Separator, TaskName};
+
+    fn render(tree: &TaskTree) -> String {
+        fn walk(tree: &TaskTree, depth: usize, out: &mut String) {
+            out.push_str(&"  ".repeat(depth));
+            out.push_str(&tree.name().display(Separator::Slash).to_string());
+            if tree.is_cycle() {
+                out.push_str(" (cycle)");
+            }
+            out.push('\n');
+
+            for child in tree.children() {
+                walk(child, depth + 1, out);
+            }
+        }
+
+        let mut out = String::new();
+        walk(tree, 0, &mut out);
+        out
+    }
+
+    #[test]
+    fn composed_tasks() {
+        let context = Context::for_test(&[
+            (
+                "",
+                r#"
+                tasks.all.run = [{ task = "lint" }, { task = "pkg/build" }, "echo done"]
+                tasks.lint.run = "echo lint"
+                "#,
+            ),
+            (
+                "pkg",
+                r#"
+                tasks.build.run = [{ task = "codegen" }, "echo build"]
+                tasks.codegen.run = "echo codegen"
+                "#,
+            ),
+        ]);
+
+        let tree = context
+            .tree(&TaskName::new("/all").relative_to("").unwrap())
+            .unwrap();
+        assert_eq!(
+            render(&tree),
+            "/all\n  /lint\n  pkg/build\n    pkg/codegen\n"
+        );
+    }
+
+    #[test]
+    fn cycles_are_marked() {
+        let context = Context::for_test(&[(
+            "",
+            r#"
+            tasks.a.run = { task = "b" }
+            tasks.b.run = [{ task = "a" }, { task = "b" }]
+            "#,
+        )]);
+
+        let tree = context
+            .tree(&TaskName::new("/a").relative_to("").unwrap())
+            .unwrap();
+        assert_eq!(render(&tree), "/a\n  /b\n    /a (cycle)\n    /b (cycle)\n");
+    }
+}
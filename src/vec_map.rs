@@ -15,6 +15,10 @@ impl<T> VecMap<T> {
         self.0.len()
     }
 
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
     pub(crate) fn get<'a>(&'a self, key: &str) -> Option<&'a T> {
         self.iter()
             .find_map(|entry| (entry.0 == key).then_some(entry.1))
@@ -36,6 +40,10 @@ impl<T> VecMap<T> {
     pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, &T)> {
         self.0.iter().map(|(key, value)| (key.as_str(), value))
     }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (&str, &mut T)> {
+        self.0.iter_mut().map(|(key, value)| (key.as_str(), value))
+    }
 }
 
 impl<T> Default for VecMap<T> {
@@ -53,6 +61,15 @@ impl<T: PartialEq> PartialEq for VecMap<T> {
     }
 }
 
+impl<T> IntoIterator for VecMap<T> {
+    type IntoIter = std::vec::IntoIter<(String, T)>;
+    type Item = (String, T);
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
 impl<T> FromIterator<(String, T)> for VecMap<T> {
     fn from_iter<I: IntoIterator<Item = (String, T)>>(iter: I) -> Self {
         Self(Vec::from_iter(iter))